@@ -1,50 +1,204 @@
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
 
 use crate::{
+    client::{stream_thread_reply, ReplyEvent},
     config::{PromptSetting, CONFIG},
-    session::Session,
+    export::{export_thread, ExportFormat},
+    import::parse_import,
+    list::{list_threads, ListOptions, ListSort},
+    llm::LlmModel,
+    message::local_time_fmt,
+    session::{Message, Session, Thread},
+    show::{page_output, render_thread, ShowOptions},
 };
 
 use clap::{Parser, Subcommand};
 use itertools::Itertools;
 use uuid::Uuid;
 
+use crate::picker::Picker;
 use crate::tui::App;
 
 #[derive(Parser, Debug)]
 #[command(version)]
 struct Cli {
+    /// Without a subcommand, choose a thread from a list
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+}
+
+/// Picks out a saved thread
+#[derive(clap::Args, Debug)]
+struct ThreadArg {
+    /// The thread's index in the list, its id (or the start of it), or part of its title
+    #[arg(required_unless_present = "last")]
+    thread: Option<String>,
+
+    /// Use the thread with the most recent message
+    #[arg(long, conflicts_with = "thread")]
+    last: bool,
+}
+
+impl ThreadArg {
+    fn resolve(&self, session: &Session) -> crate::Result<Uuid> {
+        match &self.thread {
+            Some(spec) => session.find_thread(spec),
+            None => last_thread(session),
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// List all saved threads
-    List,
+    /// List saved threads, with their message counts and (estimated) token totals
+    List {
+        #[arg(
+            long,
+            value_name = "DATE",
+            help = "Only threads from this day (YYYY-MM-DD) onwards"
+        )]
+        since: Option<chrono::NaiveDate>,
+
+        #[arg(
+            long,
+            value_name = "DATE",
+            help = "Only threads up to and including this day"
+        )]
+        until: Option<chrono::NaiveDate>,
+
+        #[arg(short, long, help = "Only threads using this model")]
+        model: Option<String>,
+
+        #[arg(short, long, help = "Only threads begun with this prompt")]
+        prompt: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            value_enum,
+            default_value_t,
+            help = "Which time to sort, and filter by dates, by"
+        )]
+        sort: ListSort,
+
+        #[arg(
+            short = 'n',
+            long,
+            help = "Only list the most recent this many threads"
+        )]
+        limit: Option<usize>,
+
+        #[arg(long, help = "Print the list as JSON")]
+        json: bool,
+    },
 
     /// Start a new conversation thread
     New {
-        #[arg(short, long, help = "PromptSetting to use")]
+        #[arg(short, long, help = "Prompt to use")]
         prompt: Option<String>,
     },
 
+    /// Ask a question and print the reply, without opening the interface.
+    /// Anything piped in is sent along with the question.
+    Ask {
+        #[arg(short, long, help = "Prompt to use")]
+        prompt: Option<String>,
+
+        #[arg(short, long, help = "Model to use, instead of the prompt's")]
+        model: Option<String>,
+
+        #[arg(long, help = "Don't save the conversation")]
+        no_save: bool,
+
+        #[arg(required = true)]
+        question: Vec<String>,
+    },
+
+    /// Add a message to a saved conversation and print the reply, without opening the interface.
+    /// Anything piped in is sent along with the message.
+    #[command(allow_missing_positional = true)]
+    Reply {
+        #[command(flatten)]
+        thread: ThreadArg,
+
+        /// The message to send, in quotes if it's more than one word
+        message: String,
+    },
+
     /// Resume a previous conversation
-    Resume { index: i64 },
+    Resume {
+        #[command(flatten)]
+        thread: ThreadArg,
+    },
+
+    /// Copy a conversation into a new thread, and continue it there
+    Fork {
+        #[command(flatten)]
+        thread: ThreadArg,
+
+        #[arg(
+            short,
+            long,
+            help = "Only copy up to this message (counting from 1, not including the prompt)"
+        )]
+        message: Option<usize>,
+    },
+
+    /// Search the messages and titles of all saved threads
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+
+        #[arg(short, long, default_value_t = 20, help = "Maximum number of results")]
+        limit: usize,
+    },
+
+    /// Write a conversation to Markdown, HTML or JSON
+    Export {
+        #[command(flatten)]
+        thread: ThreadArg,
+
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Markdown)]
+        format: ExportFormat,
+
+        #[arg(short, long, help = "File to write to, instead of standard output")]
+        output: Option<PathBuf>,
+    },
+
+    /// Print a conversation, through $PAGER if there's a terminal to show it in
+    Show {
+        /// The thread's index, id or title. Without one, the thread with the most recent message is shown.
+        thread: Option<String>,
+
+        #[arg(long, help = "Print messages as they were written, without colors")]
+        raw: bool,
+
+        #[arg(long, help = "Print only the code blocks")]
+        only_code: bool,
+
+        #[arg(long, value_name = "N", help = "Print only the last N messages")]
+        last: Option<usize>,
+    },
+
+    /// Import threads from a ChatGPT conversations.json file, or a JSON export from this program
+    Import { file: PathBuf },
 
     /// Delete a conversation thread permanently
-    Delete { index: i64 },
+    Delete {
+        #[command(flatten)]
+        thread: ThreadArg,
+    },
 
     /// Delete all conversation threads
     Clear,
 }
 
-fn thread_by_index(session: &Session, index: i64) -> Option<Uuid> {
+/// The thread with the most recent message
+fn last_thread(session: &Session) -> crate::Result<Uuid> {
     session
-        .ordered_threads()
-        .get((index - 1) as usize)
-        .map(|(id, _)| *id)
-        .copied()
+        .last_thread()
+        .ok_or_else(|| invalid_cli_value("There are no saved threads").into())
 }
 
 macro_rules! prompt_yn {
@@ -82,74 +236,224 @@ fn invalid_cli_value(msg: &str) -> clap::Error {
     clap::Error::raw(clap::error::ErrorKind::InvalidValue, msg)
 }
 
+/// Find the prompt a label refers to, which can be any unambiguous prefix of it.
+/// Without a label, the default prompt is used.
+fn resolve_prompt(label: Option<&str>) -> crate::Result<PromptSetting> {
+    let Some(prompt_label) = label else {
+        return Ok(PromptSetting::default());
+    };
+
+    let matching_prompts = CONFIG.get_matching_prompts(prompt_label);
+
+    match matching_prompts.as_slice() {
+        [prompt] => Ok((*prompt).clone()),
+
+        [] => {
+            let all_prompts = CONFIG
+                .prompts()
+                .into_iter()
+                .map(|p| format!("\t{}", &p.label))
+                .sorted()
+                .join("\n");
+
+            Err(invalid_cli_value(&format!(
+                "No prompt matched '{}'. Available prompts are:\n{}",
+                prompt_label, &all_prompts
+            ))
+            .into())
+        }
+
+        _ => {
+            let err_text = [format!(
+                "Ambiguous specifier for prompt, '{}' could refer to:",
+                prompt_label
+            )]
+            .into_iter()
+            .chain(
+                matching_prompts
+                    .into_iter()
+                    .map(|p| format!("\t {}", &p.label)),
+            )
+            .join("\n");
+
+            Err(invalid_cli_value(&err_text).into())
+        }
+    }
+}
+
+/// Stream the reply to the thread's last message to standard output as it arrives.
+/// The reply is left as the thread's incoming message, to be committed if it should be kept.
+fn print_reply(thread: &mut Thread) -> crate::Result<()> {
+    let stream = stream_thread_reply(thread)?;
+    let mut stdout = io::stdout().lock();
+
+    loop {
+        // the stream closing before the reply is done means it failed partway
+        let event = stream.rx.recv().unwrap_or_else(|_| {
+            ReplyEvent::Error(crate::Error::ChannelError(
+                "Reply stream ended unexpectedly".into(),
+            ))
+        });
+
+        match event {
+            ReplyEvent::Token(s) => {
+                stdout.write_all(s.as_bytes())?;
+                stdout.flush()?;
+                thread.update(&s);
+            }
+
+            ReplyEvent::Retrying {
+                attempt,
+                max_retries,
+                delay,
+                reason,
+            } => eprintln!(
+                "Retrying ({}/{}) in {:.0}s: {}",
+                attempt,
+                max_retries,
+                delay.as_secs_f64().ceil(),
+                reason
+            ),
+
+            ReplyEvent::Done => break,
+
+            ReplyEvent::Error(e) => {
+                // end a partial reply's line, so the error isn't printed after it
+                if thread.incoming_message().is_some() {
+                    writeln!(stdout)?;
+                }
+
+                return Err(e);
+            }
+        }
+    }
+
+    if !thread
+        .incoming_message()
+        .is_some_and(|m| m.content.ends_with('\n'))
+    {
+        writeln!(stdout)?;
+    }
+
+    Ok(())
+}
+
+/// Add whatever arrived of a reply printed by `print_reply` to the thread.
+/// A reply that failed partway is kept as truncated, as it would be in the interface.
+fn commit_printed_reply(thread: &mut Thread, result: &crate::Result<()>) {
+    let committed = match result {
+        Ok(()) => thread.commit_message(),
+        Err(_) => thread.commit_truncated_message(),
+    };
+
+    // the reply itself is already committed if naming the thread fails
+    if let Err(e) = committed {
+        eprintln!("Couldn't name the thread: {}", e);
+    }
+}
+
+/// The message to send, with anything piped to standard input added after it
+fn message_with_piped_context(text: &str) -> crate::Result<String> {
+    let mut content = text.to_string();
+
+    if let Some(context) = read_piped_stdin()? {
+        content.push_str("\n\n");
+        content.push_str(context.trim_end());
+    }
+
+    Ok(content)
+}
+
+/// Read everything piped to standard input, if anything is
+fn read_piped_stdin() -> crate::Result<Option<String>> {
+    if io::stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    let mut piped = String::new();
+    io::stdin().read_to_string(&mut piped)?;
+
+    Ok(Some(piped).filter(|s| !s.trim().is_empty()))
+}
+
 pub fn run_cli() -> crate::Result<()> {
     let cli = Cli::parse();
     let mut session = Session::new()?;
     session.load_threads()?;
 
-    match &cli.command {
-        Commands::List => {
-            for (i, list_preview) in session
-                .ordered_threads()
-                .iter()
-                .filter_map(|(_, t)| t.list_preview())
-                .enumerate()
-            {
-                println!("({}) {}", i + 1, &list_preview);
+    let Some(command) = &cli.command else {
+        if let Some(thread_id) = Picker::new(&mut session).run()? {
+            let mut app = App::with_thread(session, thread_id)?;
+            app.run()?;
+        }
+
+        return Ok(());
+    };
+
+    match command {
+        Commands::List {
+            since,
+            until,
+            model,
+            prompt,
+            sort,
+            limit,
+            json,
+        } => {
+            let options = ListOptions {
+                since: *since,
+                until: *until,
+                model: model.clone(),
+                prompt: prompt.clone(),
+                sort: *sort,
+                limit: *limit,
+            };
+
+            let entries = list_threads(&session, &options);
+
+            if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&entries).map_err(anyhow::Error::from)?
+                );
+                return Ok(());
+            }
+
+            for entry in entries {
+                println!("{}", entry.to_line());
             }
         }
 
-        Commands::Resume { index } if (*index < 1 || *index > session.nonempty_count() as i64) => {
-            let cli_err = clap::error::Error::raw(clap::error::ErrorKind::InvalidValue, "");
+        Commands::Reply { thread, message } => {
+            let thread_id = thread.resolve(&session)?;
+            let content = message_with_piped_context(message)?;
 
-            return Err(cli_err.into());
+            let thread = session
+                .thread_by_id_mut(thread_id)
+                .expect("Failed to fetch thread");
+
+            thread.add_message(Message::new_user(&content));
+
+            let result = print_reply(thread);
+
+            // a message that got no reply at all isn't kept
+            if thread.incoming_message().is_some() {
+                commit_printed_reply(thread, &result);
+            } else {
+                thread.take_unanswered_message();
+            }
+
+            session.save_to_db()?;
+            result?;
         }
 
-        Commands::Resume { index } => {
-            let thread_id = thread_by_index(&session, *index).expect("Failed to fetch thread");
+        Commands::Resume { thread } => {
+            let thread_id = thread.resolve(&session)?;
             let mut app = App::with_thread(session, thread_id)?;
             app.run()?;
         }
         Commands::New { prompt } => {
-            let prompt = match prompt {
-                Some(prompt_label) => {
-                    let matching_prompts = CONFIG.get_matching_prompts(prompt_label);
-                    if let Some(prompt) = matching_prompts.first() {
-                        if matching_prompts.len() == 1 {
-                            prompt.to_owned().clone()
-                        } else {
-                            let err_text = [format!(
-                                "Ambiguous specifier for prompt, '{}' could refer to:",
-                                prompt_label
-                            )]
-                            .into_iter()
-                            .chain(
-                                matching_prompts
-                                    .into_iter()
-                                    .map(|p| format!("\t {}", &p.label)),
-                            )
-                            .join("\n");
-
-                            return Err(invalid_cli_value(&err_text).into());
-                        }
-                    } else {
-                        let all_prompts = CONFIG
-                            .prompts()
-                            .into_iter()
-                            .map(|p| format!("\t{}", &p.label))
-                            .sorted()
-                            .join("\n");
-
-                        return Err(invalid_cli_value(&format!(
-                            "No prompt matched '{}'. Available prompts are:\n{}",
-                            prompt_label, &all_prompts
-                        ))
-                        .into());
-                    }
-                }
-
-                None => PromptSetting::default(),
-            };
+            let prompt = resolve_prompt(prompt.as_deref())?;
 
             let new_thread_id = session.new_thread(&prompt)?;
 
@@ -157,9 +461,195 @@ pub fn run_cli() -> crate::Result<()> {
             app.run()?;
         }
 
-        Commands::Delete { index } => {
-            let thread = thread_by_index(&session, *index)
-                .and_then(|id| session.thread_by_id(id))
+        Commands::Ask {
+            prompt,
+            model,
+            no_save,
+            question,
+        } => {
+            let mut prompt = resolve_prompt(prompt.as_deref())?;
+
+            if let Some(model) = model {
+                prompt.model = LlmModel::from(model.clone());
+            }
+
+            let content = message_with_piped_context(&question.join(" "))?;
+
+            let mut thread = Thread::with_prompt(&prompt);
+            thread.add_message(Message::new_user(&content));
+
+            let result = print_reply(&mut thread);
+
+            if !no_save && thread.incoming_message().is_some() {
+                commit_printed_reply(&mut thread, &result);
+
+                session.threads.insert(thread.id, thread);
+                session.save_to_db()?;
+            }
+
+            result?;
+        }
+
+        Commands::Fork { thread, message } => {
+            let thread_id = thread.resolve(&session)?;
+            let thread = session
+                .thread_by_id(thread_id)
+                .expect("Failed to fetch thread");
+
+            let up_to_message = match message {
+                Some(n) => Some(
+                    n.checked_sub(1)
+                        .and_then(|i| thread.non_sys_messages().get(i).map(|m| m.id))
+                        .ok_or_else(|| {
+                            invalid_cli_value(&format!(
+                                "Message must be between 1 and {}",
+                                thread.non_sys_messages().len()
+                            ))
+                        })?,
+                ),
+                None => None,
+            };
+
+            let fork_id = session.fork_thread(thread.id, up_to_message)?;
+
+            let mut app = App::with_thread(session, fork_id)?;
+            app.run()?;
+        }
+
+        Commands::Search { query, limit } => {
+            let results = session.search(&query.join(" "), *limit)?;
+
+            if results.is_empty() {
+                println!("No results");
+                return Ok(());
+            }
+
+            let ordered = session.ordered_threads();
+
+            for (i, result) in results.iter().enumerate() {
+                let (index, (_, thread)) = ordered
+                    .iter()
+                    .find_position(|(id, _)| **id == result.thread_id)
+                    .expect("Search results are from listed threads");
+
+                let date = result
+                    .timestamp
+                    .or_else(|| thread.init_time())
+                    .map(local_time_fmt)
+                    .unwrap_or_default();
+
+                let source = match result.role {
+                    Some(role) => thread.message_display_header(role).content.to_string(),
+                    None => "Title".into(),
+                };
+
+                println!(
+                    "[{}] ({}) {} {}\n    {}: {}",
+                    i + 1,
+                    index + 1,
+                    date,
+                    thread.display_title(),
+                    source,
+                    result.ansi_snippet()
+                );
+            }
+
+            if !(io::stdin().is_terminal() && io::stdout().is_terminal()) {
+                return Ok(());
+            }
+
+            print!("Open result (1-{}, or Enter to quit): ", results.len());
+            io::stdout().flush()?;
+
+            let mut buf = String::new();
+            io::stdin().read_line(&mut buf)?;
+
+            if buf.trim().is_empty() {
+                return Ok(());
+            }
+
+            let result = buf
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| results.get(i))
+                .ok_or_else(|| invalid_cli_value(&format!("No result '{}'", buf.trim())))?;
+
+            let mut app = App::with_thread(session, result.thread_id)?;
+            if let Some(message_id) = result.message_id {
+                app.show_message(message_id)?;
+            }
+            app.run()?;
+        }
+
+        Commands::Export {
+            thread,
+            format,
+            output,
+        } => {
+            let thread_id = thread.resolve(&session)?;
+            let thread = session
+                .thread_by_id(thread_id)
+                .expect("Failed to fetch thread");
+
+            let exported = export_thread(thread, *format)?;
+
+            match output {
+                Some(path) => std::fs::write(path, exported)?,
+                None => io::stdout().write_all(exported.as_bytes())?,
+            }
+        }
+
+        Commands::Show {
+            thread,
+            raw,
+            only_code,
+            last,
+        } => {
+            let thread_id = match thread {
+                Some(spec) => session.find_thread(spec)?,
+                None => last_thread(&session)?,
+            };
+
+            let thread = session
+                .thread_by_id(thread_id)
+                .expect("Failed to fetch thread");
+
+            let options = ShowOptions {
+                only_code: *only_code,
+                last: *last,
+                color: !raw && io::stdout().is_terminal(),
+            };
+
+            page_output(&render_thread(thread, options))?;
+        }
+
+        Commands::Import { file } => {
+            let threads = parse_import(&std::fs::read_to_string(file)?)?;
+            let found_count = threads.len();
+
+            let mut imported_count = 0;
+            for thread in threads {
+                if session.import_thread(thread)? {
+                    imported_count += 1;
+                }
+            }
+
+            println!("Imported {} threads", imported_count);
+
+            if imported_count < found_count {
+                println!(
+                    "Skipped {} threads that were already imported",
+                    found_count - imported_count
+                );
+            }
+        }
+
+        Commands::Delete { thread } => {
+            let thread_id = thread.resolve(&session)?;
+            let thread = session
+                .thread_by_id(thread_id)
                 .expect("Failed to fetch thread");
 
             match prompt_yn!("Delete thread '{}'? (y/N)", thread.display_title()) {
@@ -192,3 +682,39 @@ pub fn run_cli() -> crate::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_reply(args: &[&str]) -> Result<(ThreadArg, String), clap::Error> {
+        let cli = Cli::try_parse_from(["gpt", "reply"].iter().chain(args))?;
+
+        match cli.command {
+            Some(Commands::Reply { thread, message }) => Ok((thread, message)),
+            other => panic!("Parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reply_arguments() {
+        let (thread, message) = parse_reply(&["3", "and in Python?"]).unwrap();
+        assert_eq!(thread.thread.as_deref(), Some("3"));
+        assert!(!thread.last);
+        assert_eq!(message, "and in Python?");
+
+        let (thread, message) = parse_reply(&["--last", "thanks, one more thing"]).unwrap();
+        assert_eq!(thread.thread, None);
+        assert!(thread.last);
+        assert_eq!(message, "thanks, one more thing");
+
+        // a thread is needed, and can't be given along with --last
+        assert!(parse_reply(&["hello"]).is_err());
+        assert!(parse_reply(&["3", "--last", "hi"]).is_err());
+        assert!(parse_reply(&["3", "hi", "--last"]).is_err());
+
+        // so there's no guessing where the thread ends and the message begins
+        assert!(parse_reply(&["3", "and", "in", "Python?"]).is_err());
+        assert!(parse_reply(&["--last", "hi", "there"]).is_err());
+    }
+}
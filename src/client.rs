@@ -1,40 +1,27 @@
 use crate::config::CONFIG;
-use crate::session::{Role,  Thread};
+use crate::provider::Provider;
+use crate::session::{string_preview, Message, Role, Thread};
 use anyhow::format_err;
-use crossbeam_channel::bounded;
-use crossbeam_channel::Receiver;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use futures::future::{AbortHandle, Abortable};
 use futures::StreamExt;
-use futures_util::TryStreamExt;
 use itertools::Itertools;
 use reqwest::blocking::Client as BlockingClient;
-use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::header::HeaderMap;
 use reqwest::Client as AsyncClient;
-use serde::{Deserialize, Serialize};
-use serde_json::{self, json};
+use reqwest::StatusCode;
+use serde_json::{self, Value};
+use std::time::Duration;
 
-const OPENAI_URL: &str = "https://api.openai.com/v1/chat/completions";
 pub trait HttpClient: Sized {
-    fn init() -> crate::Result<Self>;
+    fn init(headers: HeaderMap) -> crate::Result<Self>;
 }
 
 macro_rules! build_client {
-    () => {{
-        let headers: HeaderMap = [
-            (
-                header::AUTHORIZATION,
-                HeaderValue::from_str(format!("Bearer {}", CONFIG.api_key()).as_str())
-                    .expect("Failed to format auth headers"),
-            ),
-            (
-                header::CONTENT_TYPE,
-                HeaderValue::from_static("application/json"),
-            ),
-        ]
-        .into_iter()
-        .collect();
-
+    ($headers:expr) => {{
         Self::builder()
-            .default_headers(headers)
+            .default_headers($headers)
             .build()
             .map_err(|e| e.into())
     }};
@@ -42,8 +29,8 @@ macro_rules! build_client {
 macro_rules! impl_client {
     ($struct:ident) => {
         impl HttpClient for $struct {
-            fn init() -> crate::Result<Self> {
-                build_client!()
+            fn init(headers: HeaderMap) -> crate::Result<Self> {
+                build_client!(headers)
             }
         }
     };
@@ -51,88 +38,277 @@ macro_rules! impl_client {
 impl_client!(AsyncClient);
 impl_client!(BlockingClient);
 
-/// Create a reqwest::Client with the correct default authorization headers
-pub fn create_client<T>() -> crate::Result<T>
+/// Create a reqwest::Client with the correct default authorization headers for the provider
+pub fn create_client<T>(provider: &dyn Provider, api_key: Option<&str>) -> crate::Result<T>
 where
     T: HttpClient,
 {
-    T::init()
+    T::init(provider.headers(api_key)?)
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct CompletionDelta {
-    content: Option<String>,
+/// Events sent from the background thread while a reply is streamed
+#[derive(Debug)]
+pub enum ReplyEvent {
+    /// The next piece of the reply's content
+    Token(String),
+
+    /// The reply finished successfully
+    Done,
+
+    /// A request failed, and will be tried again after `delay`
+    Retrying {
+        attempt: u32,
+        max_retries: u32,
+        delay: Duration,
+        reason: String,
+    },
+
+    /// The request failed. No further events will be sent.
+    Error(crate::Error),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CompletionChoice {
-    delta: CompletionDelta,
-    finish_reason: Option<String>,
-    index: usize,
+/// Handle to a reply being streamed on a background thread.
+pub struct ReplyStream {
+    pub rx: Receiver<ReplyEvent>,
+    abort_handle: AbortHandle,
 }
 
-///Struct representing a chunk from the streaming completions API
-#[derive(Serialize, Deserialize, Debug)]
-struct CompletionChunk {
-    id: String,
-    created: usize,
-    choices: Vec<CompletionChoice>,
+impl ReplyStream {
+    /// Abort the HTTP request and stop the background thread.
+    /// No further events will be sent after this is called.
+    pub fn cancel(&self) {
+        self.abort_handle.abort();
+    }
 }
 
-impl CompletionChunk {
-    pub fn token(&self) -> Option<String> {
-        self.choices
-            .first()
-            .and_then(|c| c.delta.content.to_owned())
+pub fn stream_thread_reply(thread: &Thread) -> crate::Result<ReplyStream> {
+    if !thread.last_message().map(|m| m.is_user()).unwrap_or(false) {
+        return Err(anyhow::format_err!(
+            "The most recent messege in the thread must be from a user"
+        )
+        .into());
     }
+
+    let prompt = thread.prompt();
+    let provider = CONFIG.provider(prompt).provider();
+
+    let client =
+        create_client::<AsyncClient>(provider.as_ref(), CONFIG.api_key(prompt).as_deref())?;
+    let url = provider.chat_url(CONFIG.base_url(prompt));
+    let body = provider.request_body(&thread.model.to_string(), &thread.messages(), true);
+
+    Ok(stream_completion(
+        client,
+        provider,
+        url,
+        body,
+        RetryPolicy::from_config(),
+    ))
 }
 
-fn try_parse_chunks(input: &str) -> crate::Result<(Option<Vec<CompletionChunk>>, Option<String>)> {
-    let mut valid_chunks = Vec::new();
+/// How failed requests are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub max_delay: Duration,
+}
 
-    let mut remainder = None;
+impl RetryPolicy {
+    const BASE_DELAY: Duration = Duration::from_secs(1);
 
-    let input_lines = input
-        .lines()
-        .map(|ln| ln.trim().trim_start_matches("data:").trim())
-        .filter(|ln| !ln.is_empty())
-        .collect_vec();
+    pub fn from_config() -> Self {
+        Self {
+            max_retries: CONFIG.max_retries(),
+            max_delay: CONFIG.max_retry_delay(),
+        }
+    }
 
-    for (i, line) in input_lines.iter().enumerate() {
-        match serde_json::from_str::<CompletionChunk>(line) {
-            Ok(chunk) => valid_chunks.push(chunk),
-            Err(e) if e.is_eof() => {
-                remainder = Some(input_lines[i..].join("\n"));
+    /// How long to wait before the given retry (counting from 1).
+    /// A delay requested by the server is used if there is one, otherwise the delay doubles with each attempt.
+    fn delay(&self, attempt: u32, requested: Option<Duration>) -> Duration {
+        requested
+            .unwrap_or_else(|| Self::BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt - 1)))
+            .min(self.max_delay)
+    }
+}
 
-                break;
-            }
-            Err(e) if e.is_syntax() && *line == "[DONE]" => break,
+/// Whether a response with this status is worth trying again
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
 
-            Err(e) => return Err(anyhow::anyhow!(e).into()),
-        }
+/// Parse durations in the format OpenAI uses for rate limit headers, such as "1s", "6m0s", or "20ms"
+fn parse_reset_duration(text: &str) -> Option<Duration> {
+    let mut total = 0f64;
+    let mut rest = text.trim();
+
+    // an empty header says nothing about when to retry, rather than to retry right away
+    if rest.is_empty() {
+        return None;
     }
 
-    let return_chunks = if valid_chunks.is_empty() {
-        None
-    } else {
-        Some(valid_chunks)
-    };
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        let number: f64 = number.parse().ok()?;
+
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        total += number
+            * match unit {
+                "h" => 3600.0,
+                "m" => 60.0,
+                "s" | "" => 1.0,
+                "ms" => 0.001,
+                _ => return None,
+            };
+
+        rest = tail;
+    }
 
-    Ok((return_chunks, remainder))
+    Duration::try_from_secs_f64(total).ok()
 }
-pub fn stream_thread_reply(thread: &Thread) -> crate::Result<Receiver<Option<String>>> {
-    if !thread.last_message().map(|m| m.is_user()).unwrap_or(false) {
-        return Err(anyhow::format_err!(
-            "The most recent messege in the thread must be from a user"
-        )
-        .into());
+
+/// Find how long the server asked us to wait before retrying, if it said
+fn requested_retry_delay(headers: &HeaderMap) -> Option<Duration> {
+    let header_str = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    // Retry-After is either a number of seconds or an HTTP date
+    let retry_after = header_str("retry-after").and_then(|v| {
+        v.trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .or_else(|| {
+                let date = DateTime::parse_from_rfc2822(v).ok()?;
+                (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+            })
+    });
+
+    // wait for whichever limit resets last
+    let ratelimit_reset = ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter_map(header_str)
+        .filter_map(parse_reset_duration)
+        .max();
+
+    retry_after.or(ratelimit_reset)
+}
+
+/// Why a single attempt at a request failed
+enum AttemptError {
+    /// The request can be tried again, possibly after a delay the server asked for
+    Retryable {
+        error: crate::Error,
+        requested_delay: Option<Duration>,
+    },
+    Fatal(crate::Error),
+}
+
+impl<T: Into<crate::Error>> From<T> for AttemptError {
+    fn from(value: T) -> Self {
+        Self::Fatal(value.into())
     }
+}
 
-    let client = create_client::<AsyncClient>()?;
+/// Make a single streaming request, forwarding tokens as they arrive.
+/// Failures before the first token arrives can be retried, since nothing has been shown to the user yet.
+async fn stream_attempt(
+    client: &AsyncClient,
+    provider: &dyn Provider,
+    url: &str,
+    body: &Value,
+    tx: &Sender<ReplyEvent>,
+) -> Result<(), AttemptError> {
+    let response =
+        client
+            .post(url)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| AttemptError::Retryable {
+                error: e.into(),
+                requested_delay: None,
+            })?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let requested_delay = requested_retry_delay(response.headers());
+        let body = response.text().await.unwrap_or_default();
+        let error = api_error(provider, status, &body);
+
+        return Err(if is_retryable_status(status) {
+            AttemptError::Retryable {
+                error,
+                requested_delay,
+            }
+        } else {
+            AttemptError::Fatal(error)
+        });
+    }
 
-    let (tx, rx) = bounded(100);
+    let mut stream = response.bytes_stream();
+
+    let mut buf = String::new();
+    let mut received_any = false;
+
+    while let Some(bytes_result) = stream.next().await {
+        let bytes = match bytes_result {
+            Ok(bytes) => bytes,
+            Err(e) if !received_any => {
+                return Err(AttemptError::Retryable {
+                    error: e.into(),
+                    requested_delay: None,
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        buf.push_str(String::from_utf8_lossy(&bytes).as_ref());
+
+        let parsed = provider.parse_stream(&buf)?;
+
+        buf.clear();
+
+        if let Some(remainder) = parsed.remainder {
+            buf.push_str(&remainder);
+        }
 
-    let thread_json = thread.as_json_body();
+        for token in parsed.tokens {
+            received_any = true;
+
+            // the receiver is only dropped if the reply is no longer wanted
+            if tx.send(ReplyEvent::Token(token)).is_err() {
+                return Ok(());
+            }
+        }
+
+        if parsed.done {
+            break;
+        }
+    }
+
+    let _ = tx.send(ReplyEvent::Done);
+
+    Ok(())
+}
+
+/// Send a streaming completion request on a background thread,
+/// forwarding tokens through the returned channel as they arrive
+fn stream_completion(
+    client: AsyncClient,
+    provider: Box<dyn Provider>,
+    url: String,
+    body: Value,
+    retry_policy: RetryPolicy,
+) -> ReplyStream {
+    let (tx, rx) = bounded(100);
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
 
     let _ = std::thread::spawn(move || {
         let rt = tokio::runtime::Builder::new_current_thread()
@@ -140,51 +316,67 @@ pub fn stream_thread_reply(thread: &Thread) -> crate::Result<Receiver<Option<Str
             .build()
             .expect("Async runtime failed to start");
 
-        let res: anyhow::Result<()> = rt.block_on(async move {
-            let response = client.post(OPENAI_URL).json(&thread_json).send().await?;
-
-            let mut stream = response
-                .error_for_status()?
-                .bytes_stream()
-                .map_err(|e| anyhow::anyhow!(e));
+        let error_tx = tx.clone();
 
-            let mut buf = String::new();
+        let request = async move {
+            let mut attempt = 0u32;
 
-            let _message_tokens = String::new();
+            loop {
+                match stream_attempt(&client, provider.as_ref(), &url, &body, &tx).await {
+                    Ok(()) => return Ok(()),
 
-            while let Some(bytes_result) = stream.next().await {
-                buf.push_str(String::from_utf8_lossy(&bytes_result?).as_ref());
+                    Err(AttemptError::Retryable {
+                        error,
+                        requested_delay,
+                    }) if attempt < retry_policy.max_retries => {
+                        attempt += 1;
+                        let delay = retry_policy.delay(attempt, requested_delay);
 
-                let (parsed, remainder) = try_parse_chunks(&buf)?;
+                        let _ = tx.send(ReplyEvent::Retrying {
+                            attempt,
+                            max_retries: retry_policy.max_retries,
+                            delay,
+                            reason: error.to_string(),
+                        });
 
-                buf.clear();
-
-                if let Some(remainder) = remainder {
-                    buf.push_str(&remainder);
-                }
-
-                if let Some(chunks) = parsed {
-                    for chunk in chunks.iter() {
-                        if let Some(s) = chunk.token() {
-                            tx.send(Some(s))?;
-                        }
+                        tokio::time::sleep(delay).await;
                     }
+
+                    Err(AttemptError::Retryable { error, .. })
+                    | Err(AttemptError::Fatal(error)) => return Err(error),
                 }
             }
+        };
 
-            tx.send(None)?;
+        // dropping the request future on abort also closes the connection
+        if let Ok(Err(e)) = rt.block_on(Abortable::new(request, abort_registration)) {
+            let _ = error_tx.send(ReplyEvent::Error(e));
+        }
+    });
 
-            Ok(())
-        });
+    ReplyStream { rx, abort_handle }
+}
 
-        res.expect("Failed to spawn thread");
-    });
+/// Build an error from a failed response, using the message from the body if the provider sent one
+fn api_error(provider: &dyn Provider, status: StatusCode, body: &str) -> crate::Error {
+    let message = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| provider.error_message(&v))
+        .unwrap_or_else(|| match body.trim() {
+            "" => status.canonical_reason().unwrap_or("Unknown error").into(),
+            text => string_preview(text, 200).into(),
+        });
 
-    Ok(rx)
+    crate::Error::ApiError { status, message }
 }
 
 pub fn fetch_thread_name(thread: &Thread) -> crate::Result<String> {
-    let client = create_client::<BlockingClient>()?;
+    let prompt_setting = thread.prompt();
+    let provider = CONFIG.provider(prompt_setting).provider();
+    let client = create_client::<BlockingClient>(
+        provider.as_ref(),
+        CONFIG.api_key(prompt_setting).as_deref(),
+    )?;
 
     let chat_content = thread
         .messages()
@@ -206,59 +398,260 @@ pub fn fetch_thread_name(thread: &Thread) -> crate::Result<String> {
         Each title should be no more than 100 characters in length.
         Your response should consist of the title and nothing else.";
 
-    let body = json!({
-    "model" : "gpt-3.5-turbo",
-    "messages": [
-        {
-        "role" : "system",
-        "content" : prompt
-        },
-        {
-            "role" : "user",
-            "content" : &chat_content
-        }]
-    });
+    let messages = [
+        Message::new(Role::System, prompt, Utc::now()),
+        Message::new(Role::User, &chat_content, Utc::now()),
+    ];
 
-    let response: serde_json::Value = client.post(OPENAI_URL).json(&body).send()?.json()?;
+    let body = provider.request_body(
+        &CONFIG.title_model(prompt_setting),
+        &messages.iter().collect_vec(),
+        false,
+    );
 
-    let title = response
-        .pointer("/choices/0/message/content")
-        .and_then(|s| s.as_str())
+    let url = provider.chat_url(CONFIG.base_url(prompt_setting));
+    let response = client.post(url).json(&body).send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        return Err(api_error(provider.as_ref(), status, &body));
+    }
+
+    let response: serde_json::Value = response.json()?;
+
+    let title = provider
+        .parse_response(&response)
         .ok_or(format_err!("Could not parse JSON response"))?;
 
-    Ok(title.into())
+    Ok(title)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::provider::{Ollama, OpenAi};
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    const NO_RETRY: RetryPolicy = RetryPolicy {
+        max_retries: 0,
+        max_delay: Duration::ZERO,
+    };
+
+    /// A canned HTTP response: status line, extra headers, and body
+    type CannedResponse = (&'static str, &'static str, &'static str);
+
+    /// Serve canned HTTP responses on a local port, one per connection, returning the base URL
+    fn serve(responses: Vec<CannedResponse>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for (status, headers, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                // consume the request so the client doesn't see a reset connection
+                let mut content_length = 0usize;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let mut request_body = vec![0u8; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: text/event-stream\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    headers,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        base_url
+    }
+
+    /// Serve a single canned HTTP response on a local port, returning the base URL
+    fn serve_once(status: &'static str, body: &'static str) -> String {
+        serve(vec![(status, "", body)])
+    }
+
+    const STREAM_BODY: &str = concat!(
+        r#"data: {"id":"1","created":0,"choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+        "\n\n",
+        r#"data: {"id":"1","created":0,"choices":[{"index":0,"delta":{"content":" there"},"finish_reason":null}]}"#,
+        "\n\n",
+        "data: [DONE]\n\n"
+    );
+
+    fn collect_tokens(reply: &ReplyStream) -> String {
+        reply
+            .rx
+            .iter()
+            .filter_map(|event| match event {
+                ReplyEvent::Token(t) => Some(Some(t)),
+                ReplyEvent::Retrying { .. } => None,
+                _ => Some(None),
+            })
+            .map_while(|t| t)
+            .collect()
+    }
 
     #[test]
-    fn test_parse_chunks() {
-        let data = r#"
-         data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1694268190,"model":"gpt-3.5-turbo-0613", "system_fingerprint": "fp_44709d6fcb", "choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}
-data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1694268190,"model":"gpt-3.5-turbo-0613", "system_fingerprint": "fp_44709d6fcb", "choices":[{"index":0,"delta":{"content":"!"},"finish_reason":null}]}
-data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1694268190,"model":"gpt-3.5-turbo-0613", "system_fingerprint": "fp_44709d6fcb", "choices":[{"index":0,"delta":{"content":" today"},"finish_reason":null}]}
-{"id":"chatcmpl-123","object":"chat.completion.chunk", "c
-        "#;
+    fn test_chat_url() {
+        assert_eq!(
+            OpenAi.chat_url("http://localhost:8080/v1/"),
+            "http://localhost:8080/v1/chat/completions"
+        );
+        assert_eq!(
+            Ollama.chat_url("http://localhost:11434"),
+            "http://localhost:11434/api/chat"
+        );
+    }
 
-        let (parsed, remaining) = try_parse_chunks(data).unwrap();
+    #[test]
+    fn test_stream_from_local_server() {
+        let base_url = serve_once("200 OK", STREAM_BODY);
+
+        let reply = stream_completion(
+            AsyncClient::new(),
+            Box::new(OpenAi),
+            OpenAi.chat_url(&base_url),
+            json!({"model": "local", "messages": [], "stream": true}),
+            NO_RETRY,
+        );
 
-        let parsed = parsed.unwrap();
-        let remaining = remaining.unwrap();
+        assert_eq!(collect_tokens(&reply), "Hello there");
+    }
 
+    #[test]
+    fn test_retry_after_rate_limit() {
+        let base_url = serve(vec![
+            ("429 Too Many Requests", "Retry-After: 0\r\n", "{}"),
+            ("503 Service Unavailable", "", ""),
+            ("200 OK", "", STREAM_BODY),
+        ]);
+
+        let reply = stream_completion(
+            AsyncClient::new(),
+            Box::new(OpenAi),
+            OpenAi.chat_url(&base_url),
+            json!({"model": "local", "messages": [], "stream": true}),
+            RetryPolicy {
+                max_retries: 2,
+                max_delay: Duration::from_millis(10),
+            },
+        );
+
+        assert_eq!(collect_tokens(&reply), "Hello there");
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            max_delay: Duration::from_secs(10),
+        };
+
+        assert_eq!(policy.delay(1, None), Duration::from_secs(1));
+        assert_eq!(policy.delay(3, None), Duration::from_secs(4));
+        assert_eq!(policy.delay(5, None), Duration::from_secs(10));
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(7))),
+            Duration::from_secs(7)
+        );
+
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            parse_reset_duration("1.5s"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_reset_duration("soon"), None);
+        assert_eq!(parse_reset_duration(""), None);
+        assert_eq!(parse_reset_duration("  "), None);
+
+        // an empty reset header leaves the delay to the usual backoff
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset-requests", "".parse().unwrap());
+        assert_eq!(requested_retry_delay(&headers), None);
+
+        headers.insert("x-ratelimit-reset-requests", "1s".parse().unwrap());
+        headers.insert("x-ratelimit-reset-tokens", "2m".parse().unwrap());
+        assert_eq!(
+            requested_retry_delay(&headers),
+            Some(Duration::from_secs(120))
+        );
+
+        headers.insert("retry-after", "3".parse().unwrap());
         assert_eq!(
-            remaining.as_str(),
-            r#"{"id":"chatcmpl-123","object":"chat.completion.chunk", "c"#
+            requested_retry_delay(&headers),
+            Some(Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn test_error_status_is_reported() {
+        let body = r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "code": "invalid_api_key"}}"#;
+
+        let base_url = serve_once("401 Unauthorized", body);
+
+        let reply = stream_completion(
+            AsyncClient::new(),
+            Box::new(OpenAi),
+            OpenAi.chat_url(&base_url),
+            json!({"model": "local", "messages": [], "stream": true}),
+            NO_RETRY,
         );
 
-        for (token, expected) in parsed
-            .into_iter()
-            .map(|chunk| chunk.token())
-            .zip(["", "!", " today"].into_iter())
-        {
-            assert!(token.is_some());
-            assert_eq!(token.unwrap().as_str(), expected);
+        match reply.rx.recv().unwrap() {
+            ReplyEvent::Error(crate::Error::ApiError { status, message }) => {
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(message, "Incorrect API key provided");
+            }
+            other => panic!("Expected an API error, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_cancel_stalled_stream() {
+        // accepts the connection but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            std::thread::sleep(std::time::Duration::from_secs(30));
+        });
+
+        let reply = stream_completion(
+            AsyncClient::new(),
+            Box::new(OpenAi),
+            OpenAi.chat_url(&base_url),
+            json!({"model": "local", "messages": [], "stream": true}),
+            NO_RETRY,
+        );
+
+        reply.cancel();
+
+        // the worker drops its sender once the request is aborted
+        let res = reply.rx.recv_timeout(std::time::Duration::from_secs(5));
+        assert!(matches!(
+            res,
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected)
+        ));
+    }
 }
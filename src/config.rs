@@ -3,7 +3,9 @@ use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, fs, path::PathBuf};
 
-pub use crate::llm::PromptSetting;
+pub use crate::llm::{PromptSetting, ProviderKind};
+
+use crate::highlight::{self, Highlighting};
 use toml;
 
 lazy_static::lazy_static! {
@@ -55,6 +57,14 @@ const ANSI_COLORS: [&str; 16] = [
     "white",
 ];
 
+/// Model used to generate thread titles when talking to the default OpenAI endpoint
+const DEFAULT_TITLE_MODEL: &str = "gpt-3.5-turbo";
+
+const DEFAULT_CONTEXT_SIZE: usize = 4_096;
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_MAX_RETRY_DELAY_SECS: f64 = 30.0;
+
 mod default_config {
     // This is so the initial config file can contain explanatory comments
     pub(super) const DEFAULT_CONFIG_TOML: &str =
@@ -66,6 +76,12 @@ pub struct Config {
     syntax_theme: String,
     editor: Option<String>,
     api_key_var: Option<String>,
+    provider: Option<ProviderKind>,
+    base_url: Option<String>,
+    title_model: Option<String>,
+    default_context_size: Option<usize>,
+    max_retries: Option<u32>,
+    max_retry_delay: Option<f64>,
     prompts: HashSet<PromptSetting>,
 }
 
@@ -98,6 +114,63 @@ impl Config {
             .collect()
     }
 
+    /// Get the provider to use with the given prompt.
+    /// The prompt's own `provider` takes priority over the global setting.
+    pub fn provider(&self, prompt: &PromptSetting) -> ProviderKind {
+        prompt.provider.or(self.provider).unwrap_or_default()
+    }
+
+    /// Whether the global settings (`base_url`, `api_key_var`) apply to this prompt.
+    /// They only do if the prompt uses the same provider they were written for.
+    fn uses_global_provider(&self, prompt: &PromptSetting) -> bool {
+        self.provider(prompt) == self.provider.unwrap_or_default()
+    }
+
+    /// Get the base URL of the API to use with the given prompt.
+    /// The prompt's own `base_url` takes priority over the global setting,
+    /// which takes priority over the provider's default.
+    pub fn base_url<'a>(&'a self, prompt: &'a PromptSetting) -> &'a str {
+        prompt
+            .base_url
+            .as_deref()
+            .or(self
+                .base_url
+                .as_deref()
+                .filter(|_| self.uses_global_provider(prompt)))
+            .unwrap_or(self.provider(prompt).default_base_url())
+    }
+
+    /// Context window size, in tokens, assumed for models without known metadata
+    pub fn default_context_size(&self) -> usize {
+        self.default_context_size.unwrap_or(DEFAULT_CONTEXT_SIZE)
+    }
+
+    /// How many times a failed request is retried before giving up
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES)
+    }
+
+    /// The longest to wait between retries, even if the server asks for longer
+    pub fn max_retry_delay(&self) -> std::time::Duration {
+        std::time::Duration::try_from_secs_f64(
+            self.max_retry_delay.unwrap_or(DEFAULT_MAX_RETRY_DELAY_SECS),
+        )
+        .unwrap_or_default()
+    }
+
+    /// Get the model used to generate thread titles.
+    /// If not set, OpenAI's endpoint uses a cheaper model, and any other endpoint
+    /// falls back to the model of the thread itself.
+    pub fn title_model(&self, prompt: &PromptSetting) -> String {
+        let default_openai = ProviderKind::OpenAi.default_base_url();
+
+        match self.title_model.as_deref() {
+            Some(model) => model.into(),
+            None if self.base_url(prompt) == default_openai => DEFAULT_TITLE_MODEL.into(),
+            None => prompt.model.to_string(),
+        }
+    }
+
     pub fn data_dir(&self) -> &'static PathBuf {
         &DATA_DIR
     }
@@ -106,18 +179,36 @@ impl Config {
         &CONFIG_DIR
     }
 
-    #[cfg(feature = "comptime-key")]
-    pub fn api_key(&self) -> String {
-        std::env!("OPENAI_API_KEY").into()
+    /// Name of the environment variable holding the API key for the given prompt's provider
+    fn api_key_var(&self, prompt: &PromptSetting) -> Option<&str> {
+        self.api_key_var
+            .as_deref()
+            .filter(|_| self.uses_global_provider(prompt))
+            .or(self.provider(prompt).default_api_key_var())
+    }
+
+    /// Whether the given prompt uses the OpenAI key compiled into the binary
+    fn uses_comptime_key(&self, prompt: &PromptSetting) -> bool {
+        cfg!(feature = "comptime-key")
+            && self.provider(prompt) == ProviderKind::OpenAi
+            && self.api_key_var.is_none()
     }
 
-    #[cfg(not(feature = "comptime-key"))]
-    pub fn api_key(&self) -> String {
-        let key_varname = self.api_key_var.as_deref().unwrap_or("OPENAI_API_KEY");
+    /// Get the API key for the given prompt's provider.
+    /// Returns None if the provider doesn't need one.
+    pub fn api_key(&self, prompt: &PromptSetting) -> Option<String> {
+        #[cfg(feature = "comptime-key")]
+        if self.uses_comptime_key(prompt) {
+            return Some(std::env!("OPENAI_API_KEY").into());
+        }
 
-        std::env::var_os(key_varname)
-            .map(|s| s.to_string_lossy().to_string())
-            .expect("No API key was found in the environment")
+        let key_varname = self.api_key_var(prompt)?;
+
+        Some(
+            std::env::var_os(key_varname)
+                .map(|s| s.to_string_lossy().to_string())
+                .expect("No API key was found in the environment"),
+        )
     }
 
     pub fn load() -> anyhow::Result<Self> {
@@ -131,8 +222,24 @@ impl Config {
             toml::from_str(&loaded_config_str)?
         };
 
-        // panics if api key is not present
-        let _ = loaded_config.api_key();
+        // confirm an api key is present for every provider in use
+        for prompt in loaded_config
+            .prompts
+            .iter()
+            .chain(std::iter::once(&PromptSetting::default()))
+        {
+            if let Some(key_varname) = loaded_config.api_key_var(prompt) {
+                if std::env::var_os(key_varname).is_none()
+                    && !loaded_config.uses_comptime_key(prompt)
+                {
+                    return Err(format_err!(
+                        "No API key for provider '{}' was found in the environment variable {}",
+                        loaded_config.provider(prompt),
+                        key_varname
+                    ));
+                }
+            }
+        }
 
         // confirm any user-set colors are valid
         if let Some(
@@ -154,6 +261,25 @@ impl Config {
             return Err(err);
         }
 
+        // confirm the theme exists, counting any extra ones in the config directory,
+        // then use it for highlighting from here on
+        highlight::install(Highlighting::load(
+            CONFIG_DIR.as_path(),
+            &loaded_config.syntax_theme,
+        )?);
+
+        // confirm all base urls can be parsed
+        for base_url in loaded_config
+            .prompts
+            .iter()
+            .filter_map(|p| p.base_url.as_deref())
+            .chain(loaded_config.base_url.as_deref())
+        {
+            if let Err(e) = reqwest::Url::parse(base_url) {
+                return Err(format_err!("Invalid base_url '{}': {}", base_url, e));
+            }
+        }
+
         Ok(loaded_config)
     }
 
@@ -1,5 +1,5 @@
 use crate::config::CONFIG;
-use crate::llm::LlmModel;
+use crate::llm::{LlmModel, PromptSetting};
 use crate::session::{Message, Role, Thread};
 
 use rusqlite::OptionalExtension;
@@ -7,18 +7,27 @@ use rusqlite::{params, Connection};
 
 use uuid::Uuid;
 
+mod migrations;
+
 const SCHEMA_CMD: &str = r#"
     CREATE TABLE thread(
         id VARCHAR PRIMARY KEY,
-        model VARCHAR
+        model VARCHAR,
+        head VARCHAR,
+        forked_from VARCHAR,
+        prompt TEXT
     );
 
     CREATE TABLE message(
+      id VARCHAR PRIMARY KEY,
+      parent_id VARCHAR,
       thread_id VARCHAR,
       role INTEGER,
       content VARCHAR,
       timestamp FLOAT,
-      FOREIGN KEY (thread_id) REFERENCES thread (id)
+      truncated INTEGER NOT NULL DEFAULT 0,
+      FOREIGN KEY (thread_id) REFERENCES thread (id),
+      FOREIGN KEY (parent_id) REFERENCES message (id)
     );
 
     CREATE TABLE title(
@@ -28,24 +37,197 @@ const SCHEMA_CMD: &str = r#"
 
 "#;
 
+/// Full-text indexes over message contents and titles, kept up to date by triggers
+const SEARCH_SCHEMA_CMD: &str = r#"
+    CREATE VIRTUAL TABLE message_fts USING fts5(
+      content,
+      content='message',
+      content_rowid='rowid'
+    );
+
+    CREATE TRIGGER message_fts_insert AFTER INSERT ON message BEGIN
+      INSERT INTO message_fts (rowid, content) VALUES (new.rowid, new.content);
+    END;
+
+    CREATE TRIGGER message_fts_delete AFTER DELETE ON message BEGIN
+      INSERT INTO message_fts (message_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+    END;
+
+    CREATE TRIGGER message_fts_update AFTER UPDATE OF content ON message
+    WHEN old.content IS NOT new.content BEGIN
+      INSERT INTO message_fts (message_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+      INSERT INTO message_fts (rowid, content) VALUES (new.rowid, new.content);
+    END;
+
+    CREATE VIRTUAL TABLE title_fts USING fts5(
+      content,
+      content='title',
+      content_rowid='rowid'
+    );
+
+    CREATE TRIGGER title_fts_insert AFTER INSERT ON title BEGIN
+      INSERT INTO title_fts (rowid, content) VALUES (new.rowid, new.content);
+    END;
+
+    CREATE TRIGGER title_fts_delete AFTER DELETE ON title BEGIN
+      INSERT INTO title_fts (title_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+    END;
+
+    CREATE TRIGGER title_fts_update AFTER UPDATE OF content ON title
+    WHEN old.content IS NOT new.content BEGIN
+      INSERT INTO title_fts (title_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+      INSERT INTO title_fts (rowid, content) VALUES (new.rowid, new.content);
+    END;
+"#;
+
 /// Create tables
-fn setup_table_schema(conn: &Connection) -> rusqlite::Result<()> {
-    conn.execute_batch(SCHEMA_CMD)
+pub(crate) fn setup_table_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(SCHEMA_CMD)?;
+    conn.execute_batch(SEARCH_SCHEMA_CMD)?;
+    migrations::set_schema_version(conn, migrations::SCHEMA_VERSION)
 }
 
 pub fn init_db() -> anyhow::Result<Connection> {
     let db_path = CONFIG.data_dir().join("gpt.db");
 
     let requires_init = !db_path.try_exists()?;
-    let conn = Connection::open(&db_path)?;
+    let mut conn = Connection::open(&db_path)?;
 
     if requires_init {
         setup_table_schema(&conn)?;
+    } else {
+        migrations::migrate(&mut conn, Some(&db_path))?;
     }
 
     Ok(conn)
 }
 
+/// Parse an id stored in the given column
+pub(crate) fn parse_uuid(column: usize, value: &str) -> rusqlite::Result<Uuid> {
+    Uuid::parse_str(value).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+/// The configured prompt that a thread saved before prompts were stored was begun with, going by
+/// its prompt text and preferring one with the same model. The thread keeps its own model either way.
+fn legacy_prompt(prompts: &[&PromptSetting], text: &str, model: &LlmModel) -> PromptSetting {
+    let same_text = || prompts.iter().filter(|p| p.prompt.trim() == text.trim());
+
+    match same_text()
+        .find(|p| p.model == *model)
+        .or_else(|| same_text().next())
+    {
+        Some(prompt) => PromptSetting {
+            model: model.clone(),
+            ..(*prompt).clone()
+        },
+
+        None => PromptSetting {
+            model: model.clone(),
+            prompt: text.into(),
+            ..Default::default()
+        },
+    }
+}
+
+/// Load a thread from the database. Threads saved before prompts were stored are matched
+/// against `configured_prompts`, which is only called if there's such a thread.
+pub(crate) fn load_thread<'a>(
+    conn: &Connection,
+    id: Uuid,
+    configured_prompts: impl FnOnce() -> Vec<&'a PromptSetting>,
+) -> crate::Result<Thread> {
+    let id_str = id.as_simple().to_string();
+
+    let (model_label, head, forked_from, prompt_json): (
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    ) = conn
+        .prepare(r" SELECT model, head, forked_from, prompt FROM thread WHERE id = ?1 ")?
+        .query_row([&id_str], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+
+    let model = LlmModel::from_label(&model_label);
+
+    let mut stmt = conn.prepare(
+        r#"
+      
+      SELECT id, parent_id, role, content, timestamp, truncated
+      FROM message
+      WHERE thread_id = ?1
+      ORDER BY timestamp ASC
+
+    "#,
+    )?;
+
+    let messages = stmt
+        .query_and_then([&id_str], |row| -> rusqlite::Result<Message> {
+            let mut message = Message::new_from_db(
+                Role::from_num(
+                    row.get::<usize, i64>(2)?
+                        .try_into()
+                        .map_err(|_e| rusqlite::Error::InvalidColumnIndex(2))?,
+                )
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?,
+                row.get(3)?,
+                row.get(4)?,
+            );
+
+            message.id = parse_uuid(0, &row.get::<_, String>(0)?)?;
+            message.parent_id = row
+                .get::<_, Option<String>>(1)?
+                .map(|p| parse_uuid(1, &p))
+                .transpose()?;
+            message.truncated = row.get(5)?;
+
+            Ok(message)
+        })?
+        .collect::<Result<Vec<Message>, _>>()?;
+
+    let head = head.map(|h| parse_uuid(1, &h)).transpose()?;
+    let forked_from = forked_from.map(|f| parse_uuid(2, &f)).transpose()?;
+
+    let title = conn
+        .prepare("SELECT content FROM title WHERE id = ?1")?
+        .query_row([&id_str], |row| row.get::<_, String>(0))
+        .optional()?;
+
+    let mut new_thread = Thread::from_tree(messages, head, model, id);
+
+    if let Some(ref title) = title {
+        new_thread.set_title(title);
+    }
+
+    if let Some(origin) = forked_from {
+        new_thread.set_forked_from(origin);
+    }
+
+    // threads stored before prompts were saved only kept the model and the prompt text
+    let prompt = match prompt_json {
+        Some(json) => {
+            serde_json::from_str(&json).map_err(|e| crate::Error::DbRetrievalError(e.into()))?
+        }
+        None => {
+            let text = new_thread
+                .messages()
+                .first()
+                .filter(|m| m.is_system())
+                .map(|m| m.content.clone())
+                .unwrap_or_default();
+
+            legacy_prompt(&configured_prompts(), &text, &new_thread.model)
+        }
+    };
+
+    new_thread.set_prompt(prompt);
+
+    Ok(new_thread)
+}
+
 pub trait DbStore: Sized {
     type Error;
     fn from_db(conn: &Connection, id: Uuid) -> Result<Self, Self::Error>;
@@ -57,56 +239,79 @@ pub trait DbStore: Sized {
 impl DbStore for Thread {
     type Error = crate::Error;
     fn to_db(&self, conn: &mut Connection) -> Result<(), Self::Error> {
-        conn.execute(
-            "INSERT OR IGNORE INTO thread (id, model) VALUES (?1, ?2)",
-            [&self.str_id(), &self.model.to_string()],
+        let tx = conn.transaction()?;
+
+        // threads stored before prompts were saved get the prompt they were matched up with
+        tx.execute(
+            "INSERT INTO thread (id, model, forked_from, prompt) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (id) DO UPDATE SET prompt = excluded.prompt",
+            params![
+                &self.str_id(),
+                &self.model.to_string(),
+                self.forked_from().map(|f| f.as_simple().to_string()),
+                serde_json::to_string(self.prompt()).map_err(anyhow::Error::from)?,
+            ],
+        )?;
+
+        // the user may have switched to another branch since the thread was stored
+        tx.execute(
+            "UPDATE thread SET head = ?2 WHERE id = ?1",
+            params![
+                &self.str_id(),
+                self.head().map(|h| h.as_simple().to_string())
+            ],
         )?;
 
+        // the thread may have been renamed since it was stored
         if let Some(title) = self.thread_title() {
-            conn.execute(
-                "INSERT OR IGNORE INTO title (id, content) VALUES (?1, ?2)",
+            tx.execute(
+                "INSERT INTO title (id, content) VALUES (?1, ?2)
+                ON CONFLICT (id) DO UPDATE SET content = excluded.content",
                 [&self.str_id(), title],
             )?;
         }
 
-        // get the most recent message in the db for this thread
-        //
-        let last_ts_result: rusqlite::Result<f64> = conn
-            .prepare(
-                r#"
-            SELECT timestamp FROM message 
-            WHERE thread_id = ?1
-            ORDER BY timestamp DESC
-            LIMIT 1
-            "#,
-            )?
-            .query_row([&self.str_id()], |row| row.get(0));
-
-        let messages_to_store: Vec<&Message> = match last_ts_result {
-            Ok(n) => Ok(self
-                .messages()
-                .into_iter()
-                .filter(|m| m.timestamp_epoch() > n)
-                .collect()),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(self.messages()),
-            Err(e) => Err(e),
-        }?;
-
-        let tx = conn.transaction()?;
-
         {
-            let mut tx_stmt = tx.prepare(
-            r#"INSERT INTO message (thread_id, role, content, timestamp) VALUES (?1, ?2, ?3, ?4)"#,
-        )?;
+            // messages are matched up by id, so changes to ones already stored are saved too
+            let mut upsert_stmt = tx.prepare(
+                r#"
+                INSERT INTO message (id, parent_id, thread_id, role, content, timestamp, truncated)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT (id) DO UPDATE SET
+                    parent_id = excluded.parent_id,
+                    content = excluded.content,
+                    truncated = excluded.truncated
+                "#,
+            )?;
 
-            for message in messages_to_store {
-                tx_stmt.execute(params![
+            for message in self.all_messages() {
+                upsert_stmt.execute(params![
+                    message.id.as_simple().to_string(),
+                    message.parent_id.map(|p| p.as_simple().to_string()),
                     &self.str_id(),
                     message.role.to_num(),
                     &message.content,
                     message.timestamp_epoch(),
+                    message.truncated,
                 ])?;
             }
+
+            // and any that were removed from the thread since it was last stored are deleted
+            let stored_ids: Vec<String> = tx
+                .prepare("SELECT id FROM message WHERE thread_id = ?1")?
+                .query_map([&self.str_id()], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+
+            let mut delete_stmt = tx.prepare("DELETE FROM message WHERE id = ?1")?;
+
+            for stored_id in stored_ids {
+                let removed =
+                    Uuid::parse_str(&stored_id).map_or(true, |id| self.message_by_id(id).is_none());
+
+                if removed {
+                    delete_stmt.execute([&stored_id])?;
+                }
+            }
         }
 
         tx.commit()?;
@@ -115,56 +320,7 @@ impl DbStore for Thread {
     }
 
     fn from_db(conn: &Connection, id: Uuid) -> Result<Self, Self::Error> {
-        let id_str = id.as_simple().to_string();
-
-        let model_label: String = conn
-            .prepare(r" SELECT model FROM thread WHERE id = ?1 ")?
-            .query_row([&id_str], |row| row.get(0))?;
-
-        let model = LlmModel::from_label(&model_label).ok_or_else(|| {
-            crate::Error::DbRetrievalError(
-                anyhow::format_err!("{} is not a valid model", &model_label).into(),
-            )
-        })?;
-
-        let mut stmt = conn.prepare(
-            r#"
-          
-          SELECT role, content, timestamp
-          FROM message
-          WHERE thread_id = ?1
-          ORDER BY timestamp ASC
-
-        "#,
-        )?;
-
-        let messages: Vec<Message> = stmt
-            .query_and_then([&id_str], |row| -> rusqlite::Result<Message> {
-                Ok(Message::new_from_db(
-                    Role::from_num(
-                        row.get::<usize, i64>(0)?
-                            .try_into()
-                            .map_err(|_e| rusqlite::Error::InvalidColumnIndex(0))?,
-                    )
-                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?,
-                    row.get(1)?,
-                    row.get(2)?,
-                ))
-            })?
-            .collect::<Result<Vec<Message>, _>>()?;
-
-        let title = conn
-            .prepare("SELECT content FROM title WHERE id = ?1")?
-            .query_row([&id_str], |row| row.get::<_, String>(0))
-            .optional()?;
-
-        let mut new_thread = Thread::new(messages, model, id);
-
-        if let Some(ref title) = title {
-            new_thread.set_title(title);
-        }
-
-        Ok(new_thread)
+        load_thread(conn, id, || CONFIG.prompts())
     }
 
     fn drop_from_db(&self, conn: &mut Connection) -> Result<bool, Self::Error> {
@@ -200,3 +356,232 @@ impl DbStore for Thread {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use itertools::Itertools;
+
+    fn message_at(role: Role, text: &str, secs: i64) -> Message {
+        Message::new(
+            role,
+            text,
+            DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_alternatives_round_trip() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let mut thread = Thread::new(
+            vec![
+                message_at(Role::System, "prompt", 0),
+                message_at(Role::User, "question", 1),
+                message_at(Role::Assistant, "first", 2),
+            ],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+        thread.to_db(&mut conn).unwrap();
+
+        thread.regenerate_reply().unwrap();
+        thread.add_message(message_at(Role::Assistant, "second", 3));
+        thread.to_db(&mut conn).unwrap();
+
+        let loaded = Thread::from_db(&conn, thread.id).unwrap();
+        assert_eq!(loaded.last_message().unwrap().content, "second");
+        assert_eq!(loaded.alternatives().len(), 1);
+        assert_eq!(loaded.alternatives()[0].content, "first");
+
+        // switching back is saved too
+        thread.cycle_alternative(true);
+        thread.to_db(&mut conn).unwrap();
+
+        let loaded = Thread::from_db(&conn, thread.id).unwrap();
+        assert_eq!(loaded.last_message().unwrap().content, "first");
+        assert_eq!(loaded.messages().len(), 3);
+    }
+
+    #[test]
+    fn test_branches_round_trip() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let mut thread = Thread::new(
+            vec![
+                message_at(Role::System, "prompt", 0),
+                message_at(Role::User, "question", 1),
+                message_at(Role::Assistant, "answer", 2),
+            ],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+        thread.to_db(&mut conn).unwrap();
+
+        let original_id = thread.non_sys_messages()[0].id;
+        thread.edit_message(original_id, "edited").unwrap();
+        thread.add_message(Message::new_asst("edited answer"));
+        thread.to_db(&mut conn).unwrap();
+
+        let mut loaded = Thread::from_db(&conn, thread.id).unwrap();
+        assert_eq!(loaded.all_messages().len(), 5);
+        assert_eq!(
+            loaded
+                .messages()
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect_vec(),
+            vec!["prompt", "edited", "edited answer"]
+        );
+
+        let edited_id = loaded.non_sys_messages()[0].id;
+        assert_eq!(loaded.switch_branch(edited_id, false), Some(original_id));
+        assert_eq!(loaded.last_message().unwrap().content, "answer");
+    }
+
+    #[test]
+    fn test_prompt_round_trip() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let prompt = PromptSetting {
+            label: "Programmer".into(),
+            prompt: "You are a programmer".into(),
+            model: LlmModel::Other("llama3".into()),
+            color: Some("red".into()),
+            provider: Some(crate::llm::ProviderKind::Ollama),
+            base_url: Some("http://localhost:8080".into()),
+        };
+
+        let mut thread = Thread::new(
+            vec![message_at(Role::System, &prompt.prompt, 0)],
+            prompt.model.clone(),
+            Uuid::new_v4(),
+        );
+        thread.set_prompt(prompt.clone());
+        thread.add_message(message_at(Role::User, "question", 1));
+        thread.to_db(&mut conn).unwrap();
+
+        let loaded = Thread::from_db(&conn, thread.id).unwrap();
+        assert_eq!(loaded.prompt(), &prompt);
+    }
+
+    #[test]
+    fn test_rename_round_trip() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let mut thread = Thread::new(
+            vec![message_at(Role::User, "question", 0)],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+        thread.set_title("First title");
+        thread.to_db(&mut conn).unwrap();
+
+        thread.set_title("Second title");
+        thread.to_db(&mut conn).unwrap();
+
+        let loaded = Thread::from_db(&conn, thread.id).unwrap();
+        assert_eq!(loaded.thread_title(), Some("Second title"));
+
+        // the search index follows the rename
+        assert!(crate::search::search(&conn, "first", 10, |_| true)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            crate::search::search(&conn, "second", 10, |_| true)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    fn assert_same_messages(a: &Thread, b: &Thread) {
+        assert_eq!(a.all_messages().len(), b.all_messages().len());
+
+        for x in a.all_messages() {
+            let y = b.message_by_id(x.id).expect("Message is in both threads");
+            assert_eq!(x.parent_id, y.parent_id);
+            assert_eq!(x.role, y.role);
+            assert_eq!(x.content, y.content);
+            assert_eq!(x.timestamp, y.timestamp);
+            assert_eq!(x.truncated, y.truncated);
+        }
+    }
+
+    #[test]
+    fn test_timestamp_round_trip() {
+        for micros in [
+            0,
+            1,
+            999_999,
+            1_000_000,
+            1_700_000_001_250_000,
+            1_712_345_678_901_234,
+        ] {
+            let timestamp =
+                DateTime::from_timestamp(micros / 1_000_000, (micros % 1_000_000) as u32 * 1_000)
+                    .unwrap();
+
+            let message = Message::new(Role::User, "", timestamp);
+            let loaded = Message::new_from_db(Role::User, "".into(), message.timestamp_epoch());
+
+            assert_eq!(loaded.timestamp, timestamp);
+        }
+    }
+
+    #[test]
+    fn test_exact_round_trip() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let mut thread = Thread::new(
+            vec![
+                Message::new(Role::System, "prompt", Utc::now()),
+                Message::new_user("question"),
+            ],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+
+        let mut reply = Message::new_asst("answer");
+        reply.truncated = true;
+        thread.add_message(reply);
+        thread.to_db(&mut conn).unwrap();
+
+        // messages that don't sort after the last stored one are still saved
+        let same_time = thread.last_message().unwrap().timestamp;
+        thread.add_message(Message::new(Role::User, "same time", same_time));
+        thread.add_message(message_at(Role::Assistant, "from long ago", 0));
+        thread.to_db(&mut conn).unwrap();
+
+        let loaded = Thread::from_db(&conn, thread.id).unwrap();
+        assert_eq!(loaded.head(), thread.head());
+        assert_same_messages(&loaded, &thread);
+    }
+
+    #[test]
+    fn test_removed_messages_are_deleted() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let mut thread = Thread::new(
+            vec![message_at(Role::System, "prompt", 0)],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+        thread.add_message(message_at(Role::User, "unanswered", 1));
+        thread.to_db(&mut conn).unwrap();
+
+        thread.take_unanswered_message().unwrap();
+        thread.to_db(&mut conn).unwrap();
+
+        let loaded = Thread::from_db(&conn, thread.id).unwrap();
+        assert_same_messages(&loaded, &thread);
+        assert_eq!(loaded.all_messages().len(), 1);
+    }
+}
@@ -0,0 +1,26 @@
+-- The original schema
+CREATE TABLE thread(
+    id VARCHAR PRIMARY KEY,
+    model VARCHAR
+);
+
+CREATE TABLE message(
+  thread_id VARCHAR,
+  role INTEGER,
+  content VARCHAR,
+  timestamp FLOAT,
+  FOREIGN KEY (thread_id) REFERENCES thread (id)
+);
+
+CREATE TABLE title(
+  id VARCHAR PRIMARY KEY,
+  content TEXT
+);
+
+INSERT INTO thread (id, model) VALUES ('6f1c1f2ad2d84c4c9b9f3a51a0c1e001', 'gpt-4');
+INSERT INTO title (id, content) VALUES ('6f1c1f2ad2d84c4c9b9f3a51a0c1e001', 'Greetings');
+
+INSERT INTO message (thread_id, role, content, timestamp) VALUES
+  ('6f1c1f2ad2d84c4c9b9f3a51a0c1e001', 1, 'You are a helpful assistant', 1700000000.0),
+  ('6f1c1f2ad2d84c4c9b9f3a51a0c1e001', 2, 'Hello', 1700000001.25),
+  ('6f1c1f2ad2d84c4c9b9f3a51a0c1e001', 3, 'Hi there!', 1700000002.5);
@@ -0,0 +1,337 @@
+//! Upgrades for databases created by older versions of the program.
+//!
+//! The schema version is kept in SQLite's `user_version` pragma.
+//! Databases from before it was used are left at 0, which is the original schema.
+
+use super::SEARCH_SCHEMA_CMD;
+
+use rusqlite::{params, Connection};
+
+use itertools::Itertools;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+type Migration = fn(&Connection) -> rusqlite::Result<()>;
+
+/// Each migration upgrades the schema by one version: `MIGRATIONS[n]` goes from version n to n + 1
+/// New migrations go at the end, along with the matching change to `SCHEMA_CMD` (or `SEARCH_SCHEMA_CMD`).
+const MIGRATIONS: &[Migration] = &[upgrade_original];
+
+/// The version of the schema created by `SCHEMA_CMD`
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+pub fn set_schema_version(conn: &Connection, version: u32) -> rusqlite::Result<()> {
+    conn.pragma_update(None, "user_version", version)
+}
+
+/// Path of the backup taken before upgrading from the given version
+fn backup_path(db_path: &Path, version: u32) -> PathBuf {
+    let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S");
+
+    db_path.with_extension(format!("v{}-{}.bak", version, timestamp))
+}
+
+/// Bring the database up to date with the current schema, if it isn't already.
+/// If `db_path` is given, a copy of the database is saved next to it first.
+/// All migrations run in one transaction, so a failure leaves the database as it was.
+/// Returns the version the database was upgraded from, if it needed upgrading.
+pub fn migrate(conn: &mut Connection, db_path: Option<&Path>) -> crate::Result<Option<u32>> {
+    let version = schema_version(conn)?;
+
+    if version > SCHEMA_VERSION {
+        return Err(crate::error::other_err!(
+            "The database has schema version {}, but this version of the program only supports up to {}. Please upgrade",
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+
+    if version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    if let Some(db_path) = db_path {
+        let backup = backup_path(db_path, version);
+        conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
+    }
+
+    let tx = conn.transaction()?;
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&tx)?;
+    }
+
+    set_schema_version(&tx, SCHEMA_VERSION)?;
+    tx.commit()?;
+
+    Ok(Some(version))
+}
+
+/// Upgrade from the original schema, which kept each thread's messages as a flat list.
+/// Every message gets an id and the one before it as its parent, so each thread becomes
+/// a tree with a single branch. Threads also get a head, the thread they were forked from
+/// and their prompt, and everything is indexed for searching.
+fn upgrade_original(conn: &Connection) -> rusqlite::Result<()> {
+    let rows: Vec<(String, i64)> = conn
+        .prepare("SELECT thread_id, rowid FROM message ORDER BY thread_id, timestamp ASC")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    conn.execute_batch(
+        r#"
+        ALTER TABLE thread ADD COLUMN head VARCHAR;
+        ALTER TABLE thread ADD COLUMN forked_from VARCHAR;
+        ALTER TABLE thread ADD COLUMN prompt TEXT;
+
+        CREATE TABLE message_tree(
+          id VARCHAR PRIMARY KEY,
+          parent_id VARCHAR,
+          thread_id VARCHAR,
+          role INTEGER,
+          content VARCHAR,
+          timestamp FLOAT,
+          truncated INTEGER NOT NULL DEFAULT 0,
+          FOREIGN KEY (thread_id) REFERENCES thread (id),
+          FOREIGN KEY (parent_id) REFERENCES message_tree (id)
+        );
+        "#,
+    )?;
+
+    {
+        let mut insert_stmt = conn.prepare(
+            r#"
+            INSERT INTO message_tree (id, parent_id, thread_id, role, content, timestamp)
+            SELECT ?1, ?2, thread_id, role, content, timestamp
+            FROM message
+            WHERE rowid = ?3
+            "#,
+        )?;
+
+        let mut head_stmt = conn.prepare("UPDATE thread SET head = ?2 WHERE id = ?1")?;
+
+        for (thread_id, thread_rows) in &rows.iter().group_by(|(thread_id, _)| thread_id) {
+            let mut last: Option<String> = None;
+
+            for (_, rowid) in thread_rows {
+                let id = Uuid::new_v4().as_simple().to_string();
+                insert_stmt.execute(params![&id, last, rowid])?;
+                last = Some(id);
+            }
+
+            head_stmt.execute(params![thread_id, last])?;
+        }
+    }
+
+    conn.execute_batch(
+        r#"
+        DROP TABLE message;
+        ALTER TABLE message_tree RENAME TO message;
+        "#,
+    )?;
+
+    conn.execute_batch(SEARCH_SCHEMA_CMD)?;
+    conn.execute_batch(
+        r#"
+        INSERT INTO message_fts (message_fts) VALUES ('rebuild');
+        INSERT INTO title_fts (title_fts) VALUES ('rebuild');
+        "#,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{load_thread, setup_table_schema, DbStore};
+    use crate::llm::{LlmModel, PromptSetting};
+
+    /// A database with the original schema, with a conversation in it
+    const V0_FIXTURE: &str = include_str!("fixtures/v0.sql");
+
+    const THREAD_ID: &str = "6f1c1f2ad2d84c4c9b9f3a51a0c1e001";
+
+    fn load_fixture() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(V0_FIXTURE).unwrap();
+        conn
+    }
+
+    fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+        conn.prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")
+            .unwrap()
+            .exists([table, column])
+            .unwrap()
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<(String, String)> {
+        conn.prepare("SELECT name, type FROM pragma_table_info(?1) ORDER BY name")
+            .unwrap()
+            .query_map([table], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_migrate_original() {
+        let fresh = Connection::open_in_memory().unwrap();
+        setup_table_schema(&fresh).unwrap();
+
+        let mut conn = load_fixture();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        assert_eq!(migrate(&mut conn, None).unwrap(), Some(0));
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+
+        for table in ["thread", "message", "title"] {
+            assert_eq!(
+                columns(&conn, table),
+                columns(&fresh, table),
+                "table {}",
+                table
+            );
+        }
+
+        let thread = load_thread(&conn, Uuid::parse_str(THREAD_ID).unwrap(), Vec::new).unwrap();
+
+        assert_eq!(thread.thread_title(), Some("Greetings"));
+        assert_eq!(thread.model, LlmModel::GPT4);
+        assert_eq!(thread.prompt().prompt, "You are a helpful assistant");
+        assert_eq!(
+            thread
+                .non_sys_messages()
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect_vec(),
+            vec!["Hello", "Hi there!"]
+        );
+        assert!(!thread.last_message().unwrap().truncated);
+        assert_eq!(thread.all_messages().len(), 3);
+
+        // everything is indexed for searching
+        let first_id = thread.non_sys_messages()[0].id;
+        assert!(crate::search::search(&conn, "hello", 10, |_| true)
+            .unwrap()
+            .iter()
+            .any(|r| r.message_id == Some(first_id)));
+    }
+
+    #[test]
+    fn test_legacy_prompt_found_in_config() {
+        let programmer = PromptSetting {
+            label: "Programmer".into(),
+            prompt: "You are a programmer".into(),
+            color: Some("green".into()),
+            ..Default::default()
+        };
+
+        let helper = PromptSetting {
+            label: "Helper".into(),
+            prompt: "You are a helpful assistant".into(),
+            color: Some("red".into()),
+            model: LlmModel::GPT35Turbo,
+            ..Default::default()
+        };
+
+        let helper_gpt4 = PromptSetting {
+            label: "Helper (GPT-4)".into(),
+            model: LlmModel::GPT4,
+            ..helper.clone()
+        };
+
+        let id = Uuid::parse_str(THREAD_ID).unwrap();
+
+        let mut conn = load_fixture();
+        migrate(&mut conn, None).unwrap();
+
+        // a prompt with the same model is preferred
+        let thread = load_thread(&conn, id, || vec![&programmer, &helper, &helper_gpt4]).unwrap();
+        assert_eq!(thread.prompt(), &helper_gpt4);
+
+        // but the thread keeps its own model when only the text matches
+        let thread = load_thread(&conn, id, || vec![&programmer, &helper]).unwrap();
+        assert_eq!(thread.prompt().label, "Helper");
+        assert_eq!(thread.prompt().color(), Some("red"));
+        assert_eq!(thread.prompt().model, LlmModel::GPT4);
+
+        let thread = load_thread(&conn, id, || vec![&programmer]).unwrap();
+        assert_eq!(thread.prompt().label, PromptSetting::default().label);
+        assert_eq!(thread.prompt().prompt, "You are a helpful assistant");
+
+        // once saved again, the prompt it was matched up with is kept
+        let thread = load_thread(&conn, id, || vec![&programmer, &helper, &helper_gpt4]).unwrap();
+        thread.to_db(&mut conn).unwrap();
+
+        let thread = load_thread(&conn, id, || panic!("Prompt looked up again")).unwrap();
+        assert_eq!(thread.prompt(), &helper_gpt4);
+    }
+
+    #[test]
+    fn test_current_version_is_left_alone() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        assert_eq!(migrate(&mut conn, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+        set_schema_version(&conn, SCHEMA_VERSION + 1).unwrap();
+
+        assert!(migrate(&mut conn, None).is_err());
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        let mut conn = load_fixture();
+
+        // makes the migration fail partway through
+        conn.execute_batch("CREATE TABLE message_tree(id VARCHAR)")
+            .unwrap();
+
+        assert!(migrate(&mut conn, None).is_err());
+
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+        assert!(!has_column(&conn, "thread", "head"));
+    }
+
+    #[test]
+    fn test_backup_before_migrating() {
+        let dir = std::env::temp_dir().join(format!("gptui-test-{}", Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("gpt.db");
+
+        let mut conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(V0_FIXTURE).unwrap();
+
+        migrate(&mut conn, Some(&db_path)).unwrap();
+
+        let backups = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| p != &db_path)
+            .collect_vec();
+
+        assert_eq!(backups.len(), 1);
+
+        // the backup is the database as it was
+        let backup = Connection::open(&backups[0]).unwrap();
+        assert_eq!(schema_version(&backup).unwrap(), 0);
+        assert_eq!(
+            backup
+                .query_row("SELECT count(*) FROM message", [], |row| row
+                    .get::<_, i64>(0))
+                .unwrap(),
+            3
+        );
+
+        drop(backup);
+        drop(conn);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
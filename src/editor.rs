@@ -48,7 +48,7 @@ pub fn input_from_editor(existing_input: &str) -> crate::Result<Option<String>>
     }?;
 
     Ok(match file_contents.trim() {
-        s if s.is_empty() => None,
+        "" => None,
         s => Some(s.into()),
     })
 }
@@ -13,6 +13,12 @@ pub enum Error {
         message: String,
     },
 
+    #[error("API error ({status}): {message}")]
+    ApiError {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+
     #[error("Connection error: {base_err}")]
     ConnectionError { base_err: reqwest::Error },
 
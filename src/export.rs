@@ -0,0 +1,308 @@
+use crate::config::PromptSetting;
+use crate::llm::LlmModel;
+use crate::message::{local_time_fmt, Segment};
+use crate::session::{Message, Role, Thread};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Identifies a JSON file as a thread exported by this program
+pub const EXPORT_FORMAT_NAME: &str = "gptui";
+
+/// Bumped whenever the layout of `ThreadExport` changes
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+const HTML_STYLE: &str = r#"
+body { max-width: 50rem; margin: 2rem auto; padding: 0 1rem; font-family: sans-serif; line-height: 1.5; }
+.meta { color: #666; }
+.message { border-top: 1px solid #ddd; padding: 0.5rem 0; }
+.message h2 { font-size: 1rem; margin: 0; display: inline-block; }
+.message time { color: #888; font-size: 0.85rem; margin-left: 0.5rem; }
+.user h2 { color: #2e7d32; }
+.assistant h2 { color: #1565c0; }
+.system h2 { color: #8e24aa; }
+.text { white-space: pre-wrap; }
+.truncated { color: #888; font-style: italic; }
+pre.code { padding: 0.75rem; overflow-x: auto; }
+"#;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    #[value(name = "md")]
+    Markdown,
+
+    Html,
+
+    Json,
+}
+
+/// A message as written to a JSON export
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MessageExport {
+    pub id: Uuid,
+    pub parent_id: Option<Uuid>,
+    pub role: Role,
+    pub content: String,
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// A thread as written to a JSON export, including every branch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThreadExport {
+    pub format: String,
+    pub version: u32,
+    pub id: Uuid,
+    pub title: Option<String>,
+    pub model: LlmModel,
+    pub prompt: PromptSetting,
+    pub forked_from: Option<Uuid>,
+
+    /// The latest message of the branch that was being shown
+    pub head: Option<Uuid>,
+
+    pub messages: Vec<MessageExport>,
+}
+
+impl From<&Message> for MessageExport {
+    fn from(msg: &Message) -> Self {
+        Self {
+            id: msg.id,
+            parent_id: msg.parent_id,
+            role: msg.role,
+            content: msg.content.clone(),
+            timestamp: msg.timestamp,
+            truncated: msg.truncated,
+        }
+    }
+}
+
+impl From<&Thread> for ThreadExport {
+    fn from(thread: &Thread) -> Self {
+        Self {
+            format: EXPORT_FORMAT_NAME.into(),
+            version: EXPORT_FORMAT_VERSION,
+            id: thread.id,
+            title: thread.thread_title().map(String::from),
+            model: thread.model.clone(),
+            prompt: thread.prompt().clone(),
+            forked_from: thread.forked_from(),
+            head: thread.head(),
+            messages: thread
+                .all_messages()
+                .into_iter()
+                .map(MessageExport::from)
+                .collect(),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Name shown above a message, using the prompt's label for replies
+fn role_label(thread: &Thread, role: Role) -> String {
+    thread.message_display_header(role).content.to_string()
+}
+
+/// The thread's current branch as a Markdown document.
+/// Message text is kept as it is, so code fences come through unchanged.
+pub fn to_markdown(thread: &Thread) -> String {
+    let mut doc = format!("# {}\n\n", thread.display_title());
+
+    doc.push_str(&format!("- **Model:** {}\n", thread.model));
+    doc.push_str(&format!("- **Prompt:** {}\n", thread.prompt().label));
+
+    if let Some(init_time) = thread.init_time() {
+        doc.push_str(&format!("- **Started:** {}\n", local_time_fmt(init_time)));
+    }
+
+    for msg in thread.messages() {
+        doc.push_str(&format!(
+            "\n## {}\n*{}*\n\n",
+            role_label(thread, msg.role),
+            local_time_fmt(msg.timestamp)
+        ));
+
+        if msg.is_system() {
+            for line in msg.content.lines() {
+                doc.push_str(&format!("> {}\n", line));
+            }
+        } else {
+            doc.push_str(msg.content.trim_end());
+            doc.push('\n');
+        }
+
+        if let Some(note) = msg.truncation_note() {
+            doc.push_str(&format!("\n*{}*\n", note));
+        }
+    }
+
+    doc
+}
+
+/// The thread's current branch as a standalone HTML page, with code blocks highlighted
+pub fn to_html(thread: &Thread) -> crate::Result<String> {
+    let title = escape_html(&thread.display_title());
+
+    let mut meta = vec![
+        format!("Model: {}", escape_html(&thread.model.to_string())),
+        format!("Prompt: {}", escape_html(&thread.prompt().label)),
+    ];
+
+    if let Some(init_time) = thread.init_time() {
+        meta.push(format!("Started: {}", local_time_fmt(init_time)));
+    }
+
+    let mut body = String::new();
+
+    for msg in thread.messages() {
+        let role_class = match msg.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+        };
+
+        body.push_str(&format!(
+            "<section class=\"message {}\">\n<h2>{}</h2><time datetime=\"{}\">{}</time>\n",
+            role_class,
+            escape_html(&role_label(thread, msg.role)),
+            msg.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+            local_time_fmt(msg.timestamp)
+        ));
+
+        for segment in msg.segments() {
+            match segment {
+                Segment::Code(block) => {
+                    body.push_str(&block.highlighted_html()?);
+                    body.push('\n');
+                }
+                Segment::Text(text) => body.push_str(&format!(
+                    "<div class=\"text\">{}</div>\n",
+                    escape_html(text.trim_matches('\n'))
+                )),
+            }
+        }
+
+        if let Some(note) = msg.truncation_note() {
+            body.push_str(&format!("<p class=\"truncated\">{}</p>\n", note));
+        }
+
+        body.push_str("</section>\n");
+    }
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n<p class=\"meta\">{}</p>\n{}</body>\n</html>\n",
+        title,
+        HTML_STYLE,
+        title,
+        meta.join(" &middot; "),
+        body
+    ))
+}
+
+/// The whole thread as JSON, with every branch and all the metadata needed to import it again
+pub fn to_json(thread: &Thread) -> crate::Result<String> {
+    Ok(serde_json::to_string_pretty(&ThreadExport::from(thread))?)
+}
+
+pub fn export_thread(thread: &Thread, format: ExportFormat) -> crate::Result<String> {
+    match format {
+        ExportFormat::Markdown => Ok(to_markdown(thread)),
+        ExportFormat::Html => to_html(thread),
+        ExportFormat::Json => to_json(thread),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A short conversation with a code block in the reply, and text that needs escaping
+    fn example_thread() -> Thread {
+        let mut thread = Thread::new(
+            vec![Message::new(
+                Role::System,
+                "You are a helpful assistant",
+                Utc::now(),
+            )],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+
+        thread.add_message(Message::new_user("How do I print <b> in Rust?"));
+        thread.add_message(Message::new_asst(
+            "Like this:\n```rust\nprintln!(\"<b>\");\n```\nThat's all.",
+        ));
+        thread.set_title("Printing & escaping");
+
+        thread
+    }
+
+    #[test]
+    fn test_markdown_keeps_code_fences() {
+        let thread = example_thread();
+        let md = to_markdown(&thread);
+
+        assert!(md.starts_with("# Printing & escaping\n"));
+        assert!(md.contains("\n## User\n"));
+        assert!(md.contains("> You are a helpful assistant\n"));
+        assert!(md.contains("```rust\nprintln!(\"<b>\");\n```\nThat's all.\n"));
+    }
+
+    #[test]
+    fn test_html_escapes_and_highlights() {
+        let thread = example_thread();
+        let html = to_html(&thread).unwrap();
+
+        assert!(html.contains("<title>Printing &amp; escaping</title>"));
+        assert!(html.contains("How do I print &lt;b&gt; in Rust?"));
+        assert!(!html.contains("```"));
+
+        // the code is split into styled spans by the highlighter
+        assert!(html.contains("<pre class=\"code\""));
+        assert!(html.contains("<span style=\"color:"));
+        assert!(html.contains("<div class=\"text\">That&#39;s all.</div>"));
+    }
+
+    #[test]
+    fn test_json_has_full_metadata() {
+        let mut thread = example_thread();
+        thread.regenerate_reply().unwrap();
+        thread.add_message(Message::new_asst("Use println!"));
+
+        let json = to_json(&thread).unwrap();
+        let export: ThreadExport = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(export, ThreadExport::from(&thread));
+        assert_eq!(export.format, EXPORT_FORMAT_NAME);
+        assert_eq!(export.title.as_deref(), Some("Printing & escaping"));
+        assert_eq!(export.head, thread.head());
+
+        // every branch is kept, with exact timestamps
+        assert_eq!(export.messages.len(), 4);
+        for msg in thread.all_messages() {
+            let exported = export.messages.iter().find(|m| m.id == msg.id).unwrap();
+            assert_eq!(exported.timestamp, msg.timestamp);
+            assert_eq!(exported.parent_id, msg.parent_id);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::format_err;
+use itertools::Itertools;
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::{SyntaxSet, SyntaxSetBuilder};
+
+pub const DEFAULT_THEME: &str = "base16-eighties.dark";
+
+/// Folder in the config directory where extra `.tmTheme` files are loaded from
+pub const THEMES_DIR: &str = "themes";
+
+/// Folder in the config directory where extra `.sublime-syntax` files are loaded from
+pub const SYNTAXES_DIR: &str = "syntaxes";
+
+static HIGHLIGHTING: OnceLock<Highlighting> = OnceLock::new();
+
+/// Add the syntax definitions in `dir` to those already in `builder`
+fn load_syntaxes(mut builder: SyntaxSetBuilder, dir: &Path) -> anyhow::Result<SyntaxSet> {
+    builder
+        .add_from_folder(dir, false)
+        .map_err(|e| format_err!("Failed to load syntaxes from {}: {}", dir.display(), e))?;
+
+    Ok(builder.build())
+}
+
+/// The syntax definitions and color theme used to highlight code blocks
+pub struct Highlighting {
+    syntaxes: SyntaxSet,
+    themes: ThemeSet,
+    theme_name: String,
+}
+
+impl Default for Highlighting {
+    fn default() -> Self {
+        Self {
+            syntaxes: SyntaxSet::load_defaults_nonewlines(),
+            themes: ThemeSet::load_defaults(),
+            theme_name: DEFAULT_THEME.into(),
+        }
+    }
+}
+
+impl Highlighting {
+    /// Load syntect's built in syntaxes and themes, along with any extra ones
+    /// in the `themes` and `syntaxes` folders of `config_dir`.
+    /// Fails if there's no theme called `theme_name`.
+    pub fn load(config_dir: &Path, theme_name: &str) -> anyhow::Result<Self> {
+        let mut themes = ThemeSet::load_defaults();
+
+        let themes_dir = config_dir.join(THEMES_DIR);
+        if themes_dir.is_dir() {
+            themes.add_from_folder(&themes_dir).map_err(|e| {
+                format_err!("Failed to load themes from {}: {}", themes_dir.display(), e)
+            })?;
+        }
+
+        let syntaxes_dir = config_dir.join(SYNTAXES_DIR);
+        let syntaxes = if syntaxes_dir.is_dir() {
+            load_syntaxes(
+                SyntaxSet::load_defaults_nonewlines().into_builder(),
+                &syntaxes_dir,
+            )?
+        } else {
+            SyntaxSet::load_defaults_nonewlines()
+        };
+
+        if !themes.themes.contains_key(theme_name) {
+            return Err(format_err!(
+                "Unknown syntax_theme '{}'. The available themes are: {}",
+                theme_name,
+                themes
+                    .themes
+                    .keys()
+                    .map(|name| format!("'{}'", name))
+                    .join(", ")
+            ));
+        }
+
+        Ok(Self {
+            syntaxes,
+            themes,
+            theme_name: theme_name.into(),
+        })
+    }
+
+    pub fn syntaxes(&self) -> &SyntaxSet {
+        &self.syntaxes
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.themes.themes[&self.theme_name]
+    }
+}
+
+/// Use these syntaxes and theme from now on.
+/// Only the first call has any effect, so this should happen before any code is highlighted.
+pub fn install(highlighting: Highlighting) {
+    let _ = HIGHLIGHTING.set(highlighting);
+}
+
+/// The syntaxes and theme in use, or syntect's defaults if none were installed
+pub fn highlighting() -> &'static Highlighting {
+    HIGHLIGHTING.get_or_init(Highlighting::default)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    /// A new folder in the temp directory, removed again when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("gptui-test-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const TEST_THEME: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>name</key>
+    <string>Test</string>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict>
+                <key>background</key>
+                <string>#102030</string>
+                <key>foreground</key>
+                <string>#f0f0f0</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>
+"#;
+
+    const TEST_SYNTAX: &str = r#"%YAML 1.2
+---
+name: Zig
+file_extensions: [zig]
+scope: source.zig
+contexts:
+  main:
+    - match: \b(fn|const|var|pub)\b
+      scope: keyword.zig
+"#;
+
+    #[test]
+    fn test_unknown_theme_lists_available() {
+        let dir = TestDir::new();
+
+        let err = Highlighting::load(&dir.0, "no-such-theme")
+            .err()
+            .unwrap()
+            .to_string();
+
+        assert!(err.contains("'no-such-theme'"));
+        assert!(err.contains("'base16-eighties.dark'"));
+        assert!(err.contains("'InspiredGitHub'"));
+    }
+
+    #[test]
+    fn test_load_extra_themes() {
+        let dir = TestDir::new();
+        std::fs::create_dir_all(dir.0.join(THEMES_DIR)).unwrap();
+        std::fs::write(dir.0.join(THEMES_DIR).join("test.tmTheme"), TEST_THEME).unwrap();
+
+        let highlighting = Highlighting::load(&dir.0, "test").unwrap();
+
+        assert!(highlighting.themes.themes.contains_key(DEFAULT_THEME));
+        assert_eq!(
+            highlighting.theme().settings.background,
+            Some(syntect::highlighting::Color {
+                r: 0x10,
+                g: 0x20,
+                b: 0x30,
+                a: 0xff
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_extra_syntaxes() {
+        let dir = TestDir::new();
+        std::fs::write(dir.0.join("zig.sublime-syntax"), TEST_SYNTAX).unwrap();
+
+        // relinking all of the default syntaxes is very slow in debug builds, so start from none
+        let syntaxes = load_syntaxes(SyntaxSetBuilder::new(), &dir.0).unwrap();
+
+        assert_eq!(syntaxes.find_syntax_by_token("zig").unwrap().name, "Zig");
+    }
+}
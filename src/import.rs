@@ -0,0 +1,424 @@
+use crate::config::PromptSetting;
+use crate::export::{ThreadExport, EXPORT_FORMAT_NAME, EXPORT_FORMAT_VERSION};
+use crate::llm::LlmModel;
+use crate::message::timestamp_from_epoch;
+use crate::session::{Message, Role, Thread};
+
+use anyhow::format_err;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A conversation from the `conversations.json` file in a ChatGPT data export
+#[derive(Deserialize, Debug)]
+struct ChatGptConversation {
+    title: Option<String>,
+    create_time: Option<f64>,
+    id: Option<String>,
+    conversation_id: Option<String>,
+    default_model_slug: Option<String>,
+
+    /// The latest message of the branch that was being shown
+    current_node: Option<String>,
+
+    /// Every node of the conversation tree, by node id
+    mapping: HashMap<String, ChatGptNode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatGptNode {
+    message: Option<ChatGptMessage>,
+    parent: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatGptMessage {
+    author: ChatGptAuthor,
+    create_time: Option<f64>,
+    content: ChatGptContent,
+    metadata: Option<ChatGptMetadata>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatGptAuthor {
+    role: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatGptContent {
+    content_type: String,
+
+    #[serde(default)]
+    parts: Vec<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatGptMetadata {
+    model_slug: Option<String>,
+}
+
+impl ChatGptMessage {
+    fn role(&self) -> Option<Role> {
+        match self.author.role.as_str() {
+            "user" => Some(Role::User),
+            "assistant" => Some(Role::Assistant),
+            "system" => Some(Role::System),
+            _ => None,
+        }
+    }
+
+    /// The text of the message. Anything that isn't text, like uploaded images, is left out.
+    fn text(&self) -> Option<String> {
+        match self.content.content_type.as_str() {
+            "text" | "multimodal_text" => {
+                let text = self
+                    .content
+                    .parts
+                    .iter()
+                    .filter_map(|p| p.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                Some(text).filter(|t| !t.trim().is_empty())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ChatGptConversation {
+    /// Convert to a thread, keeping only the text messages between the user and the assistant.
+    /// Returns None if there are none.
+    fn into_thread(self) -> Option<Thread> {
+        let fallback_time = self
+            .create_time
+            .map(timestamp_from_epoch)
+            .unwrap_or_else(Utc::now);
+
+        // tool calls, hidden messages and the like are dropped, so each kept message gets a fresh id
+        let kept_ids: HashMap<&str, Uuid> = self
+            .mapping
+            .iter()
+            .filter(|(_, node)| {
+                node.message
+                    .as_ref()
+                    .is_some_and(|m| m.role().is_some() && m.text().is_some())
+            })
+            .map(|(node_id, _)| (node_id.as_str(), Uuid::new_v4()))
+            .collect();
+
+        if kept_ids.is_empty() {
+            return None;
+        }
+
+        // the nearest node at or above this one that was kept
+        let kept_ancestor = |node_id: Option<&str>| -> Option<Uuid> {
+            let mut current = node_id;
+
+            // bounded, in case the parents loop back on themselves
+            for _ in 0..=self.mapping.len() {
+                let node_id = current?;
+
+                if let Some(id) = kept_ids.get(node_id) {
+                    return Some(*id);
+                }
+
+                current = self.mapping.get(node_id)?.parent.as_deref();
+            }
+
+            None
+        };
+
+        let mut messages = Vec::new();
+        let mut models: HashMap<Uuid, String> = HashMap::new();
+
+        for (node_id, id) in kept_ids.iter() {
+            let node = &self.mapping[*node_id];
+            let Some(chatgpt_msg) = node.message.as_ref() else {
+                continue;
+            };
+
+            let (Some(role), Some(text)) = (chatgpt_msg.role(), chatgpt_msg.text()) else {
+                continue;
+            };
+
+            let timestamp = chatgpt_msg
+                .create_time
+                .map(timestamp_from_epoch)
+                .unwrap_or(fallback_time);
+
+            let mut msg = Message::new(role, &text, timestamp);
+            msg.id = *id;
+            msg.parent_id = kept_ancestor(node.parent.as_deref());
+
+            if let Some(model) = chatgpt_msg
+                .metadata
+                .as_ref()
+                .and_then(|m| m.model_slug.clone())
+            {
+                models.insert(msg.id, model);
+            }
+
+            messages.push(msg);
+        }
+
+        messages.sort_by_key(|m| m.timestamp);
+
+        let head = kept_ancestor(self.current_node.as_deref());
+
+        let id = self
+            .conversation_id
+            .as_deref()
+            .or(self.id.as_deref())
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .unwrap_or_else(Uuid::new_v4);
+
+        let mut thread = Thread::from_tree(messages, head, LlmModel::default(), id);
+
+        // the model of the latest reply shown, since it can change partway through
+        let model = thread
+            .messages()
+            .into_iter()
+            .rev()
+            .find_map(|m| models.get(&m.id).cloned())
+            .or(self.default_model_slug)
+            .map(LlmModel::from)
+            .unwrap_or_default();
+
+        let system_prompt = thread
+            .messages()
+            .into_iter()
+            .find(|m| m.is_system())
+            .map(|m| m.content.clone());
+
+        thread.model = model.clone();
+        thread.set_prompt(PromptSetting {
+            model,
+            prompt: system_prompt.unwrap_or_else(|| PromptSetting::default().prompt),
+            ..Default::default()
+        });
+
+        if let Some(title) = self.title.filter(|t| !t.trim().is_empty()) {
+            thread.set_title(&title);
+        }
+
+        Some(thread)
+    }
+}
+
+impl ThreadExport {
+    /// Convert an exported thread back into a thread.
+    /// Messages get fresh ids, so they can't clash with ones already stored.
+    pub fn into_thread(self) -> crate::Result<Thread> {
+        if self.format != EXPORT_FORMAT_NAME {
+            return Err(format_err!(
+                "Not a {} export: format is '{}'",
+                EXPORT_FORMAT_NAME,
+                self.format
+            )
+            .into());
+        }
+
+        if self.version > EXPORT_FORMAT_VERSION {
+            return Err(format_err!(
+                "Export format version {} is newer than this program supports ({})",
+                self.version,
+                EXPORT_FORMAT_VERSION
+            )
+            .into());
+        }
+
+        let new_ids: HashMap<Uuid, Uuid> = self
+            .messages
+            .iter()
+            .map(|m| (m.id, Uuid::new_v4()))
+            .collect();
+
+        let messages = self
+            .messages
+            .into_iter()
+            .map(|exported| {
+                let mut msg = Message::new(exported.role, &exported.content, exported.timestamp);
+                msg.id = new_ids[&exported.id];
+                msg.parent_id = exported.parent_id.and_then(|p| new_ids.get(&p).copied());
+                msg.truncated = exported.truncated;
+                msg
+            })
+            .collect();
+
+        let head = self.head.and_then(|h| new_ids.get(&h).copied());
+
+        let mut thread = Thread::from_tree(messages, head, self.model, self.id);
+        thread.set_prompt(self.prompt);
+
+        if let Some(title) = self.title {
+            thread.set_title(&title);
+        }
+
+        if let Some(origin) = self.forked_from {
+            thread.set_forked_from(origin);
+        }
+
+        Ok(thread)
+    }
+}
+
+fn is_chatgpt_conversation(value: &Value) -> bool {
+    value.get("mapping").is_some()
+}
+
+fn is_export(value: &Value) -> bool {
+    value.get("format").and_then(Value::as_str) == Some(EXPORT_FORMAT_NAME)
+}
+
+fn parse_value(value: Value) -> crate::Result<Option<Thread>> {
+    if is_export(&value) {
+        let export: ThreadExport = serde_json::from_value(value)?;
+        Ok(Some(export.into_thread()?))
+    } else if is_chatgpt_conversation(&value) {
+        let conversation: ChatGptConversation = serde_json::from_value(value)?;
+        Ok(conversation.into_thread())
+    } else {
+        Err(format_err!(
+            "Not a ChatGPT conversation or a {} export",
+            EXPORT_FORMAT_NAME
+        )
+        .into())
+    }
+}
+
+/// Read threads from a ChatGPT `conversations.json` file, or a JSON export from this program.
+/// Either a single conversation or a list of them is accepted.
+/// Conversations without any text messages are left out.
+pub fn parse_import(json: &str) -> crate::Result<Vec<Thread>> {
+    let values = match serde_json::from_str(json)? {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    let threads = values
+        .into_iter()
+        .map(parse_value)
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    Ok(threads.into_iter().flatten().collect())
+}
+
+/// Whether two threads start with the same message, at the same time.
+/// Used to spot a conversation that was already imported, even if its id changed.
+pub fn same_conversation(a: &Thread, b: &Thread) -> bool {
+    let first = |t: &Thread| -> Option<(DateTime<Utc>, String)> {
+        t.non_sys_messages()
+            .first()
+            .map(|m| (m.timestamp, m.content.clone()))
+    };
+
+    first(a).is_some() && first(a) == first(b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::export::to_json;
+
+    const CHATGPT_EXPORT: &str = r#"[{
+        "title": "Sorting in Rust",
+        "create_time": 1700000000.5,
+        "update_time": 1700000100.0,
+        "conversation_id": "0b6e3f1c-4a7e-4c8e-9f49-7d3a1e2c5b10",
+        "id": "0b6e3f1c-4a7e-4c8e-9f49-7d3a1e2c5b10",
+        "default_model_slug": "gpt-4",
+        "current_node": "reply-2",
+        "mapping": {
+            "root": {"id": "root", "message": null, "parent": null, "children": ["system"]},
+            "system": {"id": "system", "parent": "root", "children": ["question"], "message": {
+                "author": {"role": "system"}, "create_time": null,
+                "content": {"content_type": "text", "parts": [""]}, "metadata": {}
+            }},
+            "question": {"id": "question", "parent": "system", "children": ["reply-1", "tool"], "message": {
+                "author": {"role": "user"}, "create_time": 1700000001.25,
+                "content": {"content_type": "multimodal_text", "parts": [{"asset_pointer": "file-1"}, "How do I sort a Vec?"]},
+                "metadata": {}
+            }},
+            "reply-1": {"id": "reply-1", "parent": "question", "children": [], "message": {
+                "author": {"role": "assistant"}, "create_time": 1700000002.0,
+                "content": {"content_type": "text", "parts": ["Use `sort`."]},
+                "metadata": {"model_slug": "gpt-4"}
+            }},
+            "tool": {"id": "tool", "parent": "question", "children": ["reply-2"], "message": {
+                "author": {"role": "tool"}, "create_time": 1700000003.0,
+                "content": {"content_type": "code", "text": "search('sort vec')"},
+                "metadata": {}
+            }},
+            "reply-2": {"id": "reply-2", "parent": "tool", "children": [], "message": {
+                "author": {"role": "assistant"}, "create_time": 1700000004.0,
+                "content": {"content_type": "text", "parts": ["Call `v.sort()`."]},
+                "metadata": {"model_slug": "gpt-4o"}
+            }}
+        }
+    }]"#;
+
+    #[test]
+    fn test_parse_chatgpt_export() {
+        let threads = parse_import(CHATGPT_EXPORT).unwrap();
+        assert_eq!(threads.len(), 1);
+
+        let thread = &threads[0];
+        assert_eq!(
+            thread.id,
+            Uuid::parse_str("0b6e3f1c-4a7e-4c8e-9f49-7d3a1e2c5b10").unwrap()
+        );
+        assert_eq!(thread.thread_title(), Some("Sorting in Rust"));
+        assert_eq!(thread.model, LlmModel::from_label("gpt-4o"));
+
+        // the empty system message and the tool call are dropped, and the branch shown is kept
+        let branch = thread.messages();
+        assert_eq!(branch.len(), 2);
+        assert_eq!(branch[0].content, "How do I sort a Vec?");
+        assert_eq!(branch[0].timestamp, timestamp_from_epoch(1700000001.25));
+        assert_eq!(branch[1].content, "Call `v.sort()`.");
+
+        // the other reply is kept as an alternative
+        assert_eq!(thread.all_messages().len(), 3);
+        assert_eq!(thread.alternatives().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_gptui_export() {
+        let mut original = Thread::new(
+            vec![Message::new(Role::System, "Be brief", Utc::now())],
+            LlmModel::from_label("llama3"),
+            Uuid::new_v4(),
+        );
+        original.add_message(Message::new_user("hello"));
+        original.add_message(Message::new_asst("hi"));
+        original.set_title("Greetings");
+
+        let threads = parse_import(&to_json(&original).unwrap()).unwrap();
+        assert_eq!(threads.len(), 1);
+
+        let imported = &threads[0];
+        assert_eq!(imported.id, original.id);
+        assert_eq!(imported.model, original.model);
+        assert_eq!(imported.prompt(), original.prompt());
+        assert_eq!(imported.thread_title(), Some("Greetings"));
+        assert!(same_conversation(imported, &original));
+
+        for (a, b) in imported.messages().iter().zip(original.messages()) {
+            assert_ne!(a.id, b.id);
+            assert_eq!(a.content, b.content);
+            assert_eq!(a.timestamp, b.timestamp);
+        }
+    }
+
+    #[test]
+    fn test_parse_unknown_format() {
+        assert!(parse_import(r#"{"messages": []}"#).is_err());
+
+        let mut newer: Value = serde_json::from_str(&to_json(&Thread::default()).unwrap()).unwrap();
+        newer["version"] = (EXPORT_FORMAT_VERSION + 1).into();
+        assert!(parse_import(&newer.to_string()).is_err());
+    }
+}
@@ -0,0 +1,561 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::layout::Rect;
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Frame;
+use std::ops::Range;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// How many edits can be undone
+const UNDO_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditKind {
+    Insert,
+    Delete,
+    Other,
+}
+
+/// The text as it was before an edit, to go back to on undo
+#[derive(Debug, Clone)]
+struct Snapshot {
+    text: String,
+    cursor: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Text box for writing messages, with a cursor that can be moved anywhere in the text
+#[derive(Debug, Clone, Default)]
+pub struct InputEditor {
+    text: String,
+
+    /// Byte offset of the cursor in `text`, always on a char boundary
+    cursor: usize,
+
+    undo_stack: Vec<Snapshot>,
+
+    /// What the last edit was, so a run of typing is undone all at once
+    last_edit: Option<EditKind>,
+
+    /// First row of the wrapped text that's in view
+    scroll: usize,
+
+    /// Width the text was last laid out at, for moving up and down between rows
+    width: usize,
+}
+
+impl InputEditor {
+    /// An editor holding `text`, with the cursor at the end
+    pub fn with_text(text: &str) -> Self {
+        Self {
+            text: text.into(),
+            cursor: text.len(),
+            ..Default::default()
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Replace all of the text, leaving the cursor at the end. This can be undone.
+    pub fn set_text(&mut self, text: &str) {
+        self.begin_edit(EditKind::Other);
+        self.text = text.into();
+        self.cursor = self.text.len();
+    }
+
+    /// Take the text out, leaving the editor empty with nothing to undo
+    pub fn take(&mut self) -> String {
+        std::mem::take(self).text
+    }
+
+    /// Apply a key press to the text.
+    /// Returns false if the key isn't one the editor has a use for, so it can be handled elsewhere.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+        match key.code {
+            KeyCode::Char('z') if ctrl => {
+                self.undo();
+            }
+            KeyCode::Char('u') if ctrl => self.delete_to_line_start(),
+            KeyCode::Char('k') if ctrl => self.delete_to_line_end(),
+            KeyCode::Char('b') if alt => self.move_word_left(),
+            KeyCode::Char('f') if alt => self.move_word_right(),
+            KeyCode::Char('d') if alt => self.delete_word_after(),
+            KeyCode::Char(_) if ctrl || alt => return false,
+
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.insert_char(c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => self.insert_char(c),
+            KeyCode::Enter if !ctrl && !alt => self.insert_char('\n'),
+
+            KeyCode::Backspace if ctrl || alt => self.delete_word_before(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete if ctrl || alt => self.delete_word_after(),
+            KeyCode::Delete => self.delete(),
+
+            KeyCode::Left if ctrl => self.move_word_left(),
+            KeyCode::Right if ctrl => self.move_word_right(),
+            KeyCode::Left if !alt => self.move_to(self.prev_boundary(self.cursor)),
+            KeyCode::Right if !alt => self.move_to(self.next_boundary(self.cursor)),
+
+            KeyCode::Home if ctrl => self.move_to(0),
+            KeyCode::End if ctrl => self.move_to(self.text.len()),
+            KeyCode::Home => self.move_to(self.line_start(self.cursor)),
+            KeyCode::End => self.move_to(self.line_end(self.cursor)),
+
+            KeyCode::Up => return self.move_up(),
+            KeyCode::Down => return self.move_down(),
+
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Go back to how the text was before the last edit. Returns false if there's nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let Some(Snapshot { text, cursor }) = self.undo_stack.pop() else {
+            return false;
+        };
+
+        self.text = text;
+        self.cursor = cursor;
+        self.last_edit = None;
+        true
+    }
+
+    /// Save the text before an edit, unless the edit continues a run of the same kind
+    fn begin_edit(&mut self, kind: EditKind) {
+        if kind != EditKind::Other && self.last_edit == Some(kind) {
+            return;
+        }
+
+        self.undo_stack.push(Snapshot {
+            text: self.text.clone(),
+            cursor: self.cursor,
+        });
+
+        if self.undo_stack.len() > UNDO_LIMIT {
+            self.undo_stack.remove(0);
+        }
+
+        self.last_edit = Some(kind);
+    }
+
+    fn insert_char(&mut self, c: char) {
+        // each word typed is undone separately
+        if c.is_whitespace() {
+            self.last_edit = None;
+        }
+
+        self.begin_edit(EditKind::Insert);
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    fn delete_range(&mut self, range: Range<usize>, kind: EditKind) {
+        if range.is_empty() {
+            return;
+        }
+
+        self.begin_edit(kind);
+        self.cursor = range.start;
+        self.text.replace_range(range, "");
+    }
+
+    fn backspace(&mut self) {
+        self.delete_range(
+            self.prev_boundary(self.cursor)..self.cursor,
+            EditKind::Delete,
+        );
+    }
+
+    fn delete(&mut self) {
+        self.delete_range(
+            self.cursor..self.next_boundary(self.cursor),
+            EditKind::Delete,
+        );
+    }
+
+    fn delete_word_before(&mut self) {
+        self.delete_range(self.word_start(self.cursor)..self.cursor, EditKind::Other);
+    }
+
+    fn delete_word_after(&mut self) {
+        self.delete_range(self.cursor..self.word_end(self.cursor), EditKind::Other);
+    }
+
+    fn delete_to_line_start(&mut self) {
+        self.delete_range(self.line_start(self.cursor)..self.cursor, EditKind::Other);
+    }
+
+    /// Delete the rest of the line, or the line break if already at the end of one
+    fn delete_to_line_end(&mut self) {
+        let end = match self.line_end(self.cursor) {
+            end if end == self.cursor => self.next_boundary(self.cursor),
+            end => end,
+        };
+
+        self.delete_range(self.cursor..end, EditKind::Other);
+    }
+
+    fn move_to(&mut self, cursor: usize) {
+        self.cursor = cursor;
+        self.last_edit = None;
+    }
+
+    fn move_word_left(&mut self) {
+        self.move_to(self.word_start(self.cursor));
+    }
+
+    fn move_word_right(&mut self) {
+        self.move_to(self.word_end(self.cursor));
+    }
+
+    fn prev_boundary(&self, i: usize) -> usize {
+        self.text[..i]
+            .char_indices()
+            .next_back()
+            .map(|(j, _)| j)
+            .unwrap_or(0)
+    }
+
+    fn next_boundary(&self, i: usize) -> usize {
+        i + self.text[i..]
+            .chars()
+            .next()
+            .map(char::len_utf8)
+            .unwrap_or(0)
+    }
+
+    fn line_start(&self, i: usize) -> usize {
+        self.text[..i].rfind('\n').map(|j| j + 1).unwrap_or(0)
+    }
+
+    fn line_end(&self, i: usize) -> usize {
+        self.text[i..]
+            .find('\n')
+            .map(|j| i + j)
+            .unwrap_or(self.text.len())
+    }
+
+    /// Start of the word before `i`, skipping any spaces and punctuation in between
+    fn word_start(&self, i: usize) -> usize {
+        let mut chars = self.text[..i].char_indices().rev().peekable();
+
+        while chars.next_if(|(_, c)| !is_word_char(*c)).is_some() {}
+        while chars.next_if(|(_, c)| is_word_char(*c)).is_some() {}
+
+        chars.peek().map(|(j, c)| j + c.len_utf8()).unwrap_or(0)
+    }
+
+    /// End of the word after `i`, skipping any spaces and punctuation in between
+    fn word_end(&self, i: usize) -> usize {
+        let mut chars = self.text[i..].char_indices().peekable();
+
+        while chars.next_if(|(_, c)| !is_word_char(*c)).is_some() {}
+        while chars.next_if(|(_, c)| is_word_char(*c)).is_some() {}
+
+        chars.peek().map(|(j, _)| i + j).unwrap_or(self.text.len())
+    }
+
+    /// Byte ranges of the rows the text takes up when wrapped to `width` columns.
+    /// Line breaks aren't part of any row.
+    fn rows(&self, width: usize) -> Vec<Range<usize>> {
+        let width = width.max(1);
+        let mut rows = Vec::new();
+        let mut start = 0;
+        let mut row_width = 0;
+
+        for (i, c) in self.text.char_indices() {
+            if c == '\n' {
+                rows.push(start..i);
+                start = i + 1;
+                row_width = 0;
+                continue;
+            }
+
+            let w = char_width(c);
+
+            if row_width > 0 && row_width + w > width {
+                rows.push(start..i);
+                start = i;
+                row_width = 0;
+            }
+
+            row_width += w;
+        }
+
+        rows.push(start..self.text.len());
+        rows
+    }
+
+    /// Row and column of the cursor among `rows`
+    fn cursor_position(&self, rows: &[Range<usize>]) -> (usize, usize) {
+        let row = rows
+            .iter()
+            .rposition(|r| r.start <= self.cursor)
+            .unwrap_or(0);
+
+        let col = self.text[rows[row].start..self.cursor].width();
+
+        (row, col)
+    }
+
+    /// Move the cursor to the row above, as near the same column as it can be.
+    /// Returns false if it's already on the first row.
+    pub fn move_up(&mut self) -> bool {
+        self.move_vertically(false)
+    }
+
+    /// Move the cursor to the row below, as near the same column as it can be.
+    /// Returns false if it's already on the last row.
+    pub fn move_down(&mut self) -> bool {
+        self.move_vertically(true)
+    }
+
+    fn move_vertically(&mut self, down: bool) -> bool {
+        let width = if self.width == 0 {
+            usize::MAX
+        } else {
+            self.width
+        };
+
+        let rows = self.rows(width);
+        let (row, col) = self.cursor_position(&rows);
+
+        let target = match down {
+            true if row + 1 < rows.len() => &rows[row + 1],
+            false if row > 0 => &rows[row - 1],
+            _ => return false,
+        };
+
+        let mut cursor = target.start;
+        let mut cursor_col = 0;
+
+        for (i, c) in self.text[target.clone()].char_indices() {
+            if cursor_col + char_width(c) > col {
+                break;
+            }
+
+            cursor_col += char_width(c);
+            cursor = target.start + i + c.len_utf8();
+        }
+
+        self.move_to(cursor);
+        true
+    }
+
+    /// Lay out the text in a box `width` columns wide and `height` rows tall,
+    /// scrolling just enough to keep the cursor in view.
+    /// Returns the rows in view, and the cursor's row and column among them.
+    fn layout(&mut self, width: usize, height: usize) -> (Vec<&str>, (usize, usize)) {
+        self.width = width;
+
+        let rows = self.rows(width);
+        let (mut row, mut col) = self.cursor_position(&rows);
+        let mut row_count = rows.len();
+
+        // a cursor at the end of the text, after a full row, goes at the start of the next one.
+        // Anywhere else it's before a line break, so it stays on its row.
+        if width > 0 && col >= width && self.cursor == self.text.len() {
+            row += 1;
+            col = 0;
+            row_count = row_count.max(row + 1);
+        }
+
+        let height = height.max(1);
+
+        self.scroll = self.scroll.min(row_count.saturating_sub(height));
+
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + height {
+            self.scroll = row + 1 - height;
+        }
+
+        let visible = rows
+            .into_iter()
+            .skip(self.scroll)
+            .take(height)
+            .map(|r| &self.text[r])
+            .collect();
+
+        (visible, (row - self.scroll, col))
+    }
+
+    /// Draw the text in `block`, putting the terminal's cursor where the editor's is if `show_cursor` is set
+    pub fn render(&mut self, frame: &mut Frame, area: Rect, block: Block, show_cursor: bool) {
+        let inner = block.inner(area);
+
+        let (visible, (row, col)) = self.layout(inner.width.into(), inner.height.into());
+        let lines = visible.into_iter().map(Line::from).collect::<Vec<_>>();
+
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+
+        if show_cursor && inner.width > 0 && inner.height > 0 {
+            frame.set_cursor(inner.x + col as u16, inner.y + row as u16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn press(editor: &mut InputEditor, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        editor.handle_key(KeyEvent::new(code, modifiers))
+    }
+
+    fn type_text(editor: &mut InputEditor, text: &str) {
+        for c in text.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            };
+
+            press(editor, code, KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn test_cursor_movement_and_unicode() {
+        let mut editor = InputEditor::default();
+        type_text(&mut editor, "héllo wörld");
+
+        press(&mut editor, KeyCode::Home, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Right, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Right, KeyModifiers::NONE);
+        type_text(&mut editor, "→");
+        assert_eq!(editor.text(), "hé→llo wörld");
+
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Delete, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "hlo wörld");
+
+        press(&mut editor, KeyCode::End, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Left, KeyModifiers::NONE);
+        type_text(&mut editor, "!");
+        assert_eq!(editor.text(), "hlo wörl!d");
+
+        // keys that belong to the app are left alone
+        assert!(!press(
+            &mut editor,
+            KeyCode::Char('e'),
+            KeyModifiers::CONTROL
+        ));
+        assert!(!press(&mut editor, KeyCode::Left, KeyModifiers::ALT));
+        assert_eq!(editor.text(), "hlo wörl!d");
+    }
+
+    #[test]
+    fn test_word_motions_and_deletion() {
+        let mut editor = InputEditor::with_text("let x = foo_bar(baz);");
+
+        press(&mut editor, KeyCode::Left, KeyModifiers::CONTROL);
+        assert_eq!(&editor.text()[editor.cursor..], "baz);");
+
+        press(&mut editor, KeyCode::Left, KeyModifiers::CONTROL);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::ALT);
+        assert_eq!(editor.text(), "let foo_bar(baz);");
+
+        press(&mut editor, KeyCode::Delete, KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "let (baz);");
+
+        press(&mut editor, KeyCode::Char('f'), KeyModifiers::ALT);
+        assert_eq!(&editor.text()[editor.cursor..], ");");
+    }
+
+    #[test]
+    fn test_kill_to_line_start_and_end() {
+        let mut editor = InputEditor::with_text("first line\nsecond line\nthird");
+
+        // up from the end of "third" lands after "secon"
+        press(&mut editor, KeyCode::Up, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('u'), KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "first line\nd line\nthird");
+
+        press(&mut editor, KeyCode::Char('k'), KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "first line\n\nthird");
+
+        // at the end of a line, the line break goes
+        press(&mut editor, KeyCode::Char('k'), KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "first line\nthird");
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut editor = InputEditor::default();
+        type_text(&mut editor, "hello there");
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        editor.set_text("replaced");
+
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "hello the");
+
+        // a run of deletions is undone at once, then typing word by word
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "hello there");
+
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "hello");
+
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.text(), "");
+        assert!(!editor.undo());
+    }
+
+    #[test]
+    fn test_wrapping_and_scrolling() {
+        let mut editor = InputEditor::with_text("abcdefgh\n12\nxyz");
+
+        let (rows, cursor) = editor.layout(4, 2);
+        assert_eq!(rows, vec!["12", "xyz"]);
+        assert_eq!(cursor, (1, 3));
+
+        // moving up goes through the wrapped rows, scrolling as it goes
+        assert!(editor.move_up());
+        assert!(editor.move_up());
+        let (rows, cursor) = editor.layout(4, 2);
+        assert_eq!(rows, vec!["efgh", "12"]);
+        assert_eq!(cursor, (0, 2));
+        assert_eq!(&editor.text()[editor.cursor..], "gh\n12\nxyz");
+
+        assert!(editor.move_up());
+        assert!(!editor.move_up());
+        let (rows, cursor) = editor.layout(4, 2);
+        assert_eq!(rows, vec!["abcd", "efgh"]);
+        assert_eq!(cursor, (0, 2));
+
+        // a cursor at the end of the text, after a full row, is shown on the next
+        let mut editor = InputEditor::with_text("abcd");
+        let (rows, cursor) = editor.layout(4, 2);
+        assert_eq!(rows, vec!["abcd"]);
+        assert_eq!(cursor, (1, 0));
+
+        // but one before a line break stays at the end of its row
+        let mut editor = InputEditor::with_text("abcd\nxy");
+        editor.move_to(4);
+        let (rows, cursor) = editor.layout(4, 2);
+        assert_eq!(rows, vec!["abcd", "xy"]);
+        assert_eq!(cursor, (0, 4));
+    }
+}
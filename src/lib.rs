@@ -5,9 +5,19 @@ pub mod config;
 pub mod db;
 pub mod editor;
 pub mod error;
+pub mod export;
+pub mod highlight;
+pub mod import;
+pub mod input;
+pub mod list;
 pub mod llm;
+pub mod markdown;
 pub mod message;
+pub mod picker;
+pub mod provider;
+pub mod search;
 pub mod session;
+pub mod show;
 pub mod tui;
 
 pub use error::Error;
@@ -0,0 +1,328 @@
+use crate::message::local_time_fmt;
+use crate::session::{Session, Thread};
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Rough number of characters in a token, for estimating token counts without a tokenizer
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Which time threads are sorted and filtered by
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListSort {
+    /// When the thread was started
+    #[default]
+    Created,
+
+    /// When the most recent message was added
+    Updated,
+}
+
+/// Which threads are listed, and in what order
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    /// Only threads from this day onwards
+    pub since: Option<NaiveDate>,
+
+    /// Only threads up to and including this day
+    pub until: Option<NaiveDate>,
+
+    /// Only threads using this model
+    pub model: Option<String>,
+
+    /// Only threads begun with the prompt with this label
+    pub prompt: Option<String>,
+
+    pub sort: ListSort,
+
+    /// Only the most recent this many threads
+    pub limit: Option<usize>,
+}
+
+/// A summary of a saved thread, as printed by `list`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ListEntry {
+    /// Position in the list of all threads, counting from 1, as accepted by other commands
+    pub index: usize,
+    pub id: Uuid,
+
+    /// The start of the id, as printed
+    #[serde(skip)]
+    pub short_id: String,
+
+    pub title: String,
+    pub model: String,
+    pub prompt: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+
+    /// Messages in the current branch, not counting the prompt
+    pub messages: usize,
+
+    /// Estimated tokens in the current branch, including the prompt
+    pub tokens: usize,
+
+    pub forked_from: Option<Uuid>,
+
+    /// Index of the thread this was forked from, if it still exists
+    #[serde(skip)]
+    pub forked_from_index: Option<usize>,
+}
+
+/// Estimate how many tokens some text takes up.
+/// This is only approximate, since the real count depends on the model's tokenizer.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// The start of a local day, as a UTC time
+fn local_day_start(day: NaiveDate) -> DateTime<Utc> {
+    let midnight = day.and_hms_opt(0, 0, 0).expect("Midnight is a valid time");
+
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(|| midnight.and_utc())
+}
+
+fn list_entry(index: usize, thread: &Thread) -> ListEntry {
+    let messages = thread.messages();
+    let created = messages.first().map(|m| m.timestamp).unwrap_or_default();
+
+    ListEntry {
+        index,
+        id: thread.id,
+        short_id: thread.short_id(),
+        title: thread.display_title(),
+        model: thread.model.to_string(),
+        prompt: thread.prompt().label.clone(),
+        created,
+        updated: thread.last_update().unwrap_or(created),
+        messages: messages.iter().filter(|m| !m.is_system()).count(),
+        tokens: messages.iter().map(|m| estimate_tokens(&m.content)).sum(),
+        forked_from: thread.forked_from(),
+        forked_from_index: None,
+    }
+}
+
+impl ListEntry {
+    /// The time threads are sorted and filtered by
+    fn sort_time(&self, sort: ListSort) -> DateTime<Utc> {
+        match sort {
+            ListSort::Created => self.created,
+            ListSort::Updated => self.updated,
+        }
+    }
+
+    /// The entry as a single line, for printing
+    pub fn to_line(&self) -> String {
+        let fork_note = match (self.forked_from, self.forked_from_index) {
+            (Some(_), Some(origin_index)) => format!(" [fork of ({})]", origin_index),
+            (Some(_), None) => " [fork of a deleted thread]".into(),
+            _ => String::new(),
+        };
+
+        format!(
+            "({}) {} {} {} [{}, {} messages, ~{} tokens]{}",
+            self.index,
+            self.short_id,
+            local_time_fmt(self.created),
+            self.title,
+            self.model,
+            self.messages,
+            self.tokens,
+            fork_note
+        )
+    }
+}
+
+/// Summaries of the saved threads matching `options`, oldest first.
+/// Indices are those of `Session::ordered_threads`, so they stay the same however the list is filtered.
+pub fn list_threads(session: &Session, options: &ListOptions) -> Vec<ListEntry> {
+    let ordered = session.ordered_threads();
+
+    let since = options.since.map(local_day_start);
+    let until = options
+        .until
+        .and_then(|day| day.succ_opt())
+        .map(local_day_start);
+
+    let mut entries = ordered
+        .iter()
+        .enumerate()
+        .map(|(i, (_, thread))| {
+            let mut entry = list_entry(i + 1, thread);
+
+            entry.forked_from_index = entry.forked_from.and_then(|origin| {
+                ordered
+                    .iter()
+                    .position(|(id, _)| **id == origin)
+                    .map(|i| i + 1)
+            });
+
+            entry
+        })
+        .filter(|entry| {
+            let time = entry.sort_time(options.sort);
+
+            since.is_none_or(|since| time >= since)
+                && until.is_none_or(|until| time < until)
+                && options
+                    .model
+                    .as_ref()
+                    .is_none_or(|model| entry.model.eq_ignore_ascii_case(model))
+                && options
+                    .prompt
+                    .as_ref()
+                    .is_none_or(|label| entry.prompt.to_lowercase() == label.to_lowercase())
+        })
+        .collect::<Vec<_>>();
+
+    // the sort is stable, so ties keep their order from the full list
+    entries.sort_by_key(|entry| entry.sort_time(options.sort));
+
+    if let Some(limit) = options.limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::PromptSetting;
+    use crate::llm::LlmModel;
+    use crate::session::{Message, Role};
+
+    fn message_at(role: Role, text: &str, day: u32) -> Message {
+        let time = NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+
+        Message::new(role, text, Local.from_local_datetime(&time).unwrap().into())
+    }
+
+    fn example_session() -> Session {
+        let mut session = Session::in_memory();
+
+        for (title, model, label, started, updated) in [
+            ("Rust lifetimes", LlmModel::GPT4o, "Programmer", 1, 20),
+            ("Soup recipes", LlmModel::GPT35Turbo, "Assistant", 5, 6),
+            ("Rust macros", LlmModel::GPT4o, "Assistant", 10, 11),
+        ] {
+            let prompt = PromptSetting {
+                label: label.into(),
+                model: model.clone(),
+                ..Default::default()
+            };
+
+            let mut thread = Thread::new(
+                vec![message_at(Role::System, "prompt", started)],
+                model,
+                Uuid::new_v4(),
+            );
+            thread.set_prompt(prompt);
+            thread.set_title(title);
+            thread.add_message(message_at(Role::User, "a question", started));
+            thread.add_message(message_at(Role::Assistant, "an answer", updated));
+
+            session.threads.insert(thread.id, thread);
+        }
+
+        // an empty thread is never listed
+        let empty = Thread::new(
+            vec![message_at(Role::System, "prompt", 15)],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+        session.threads.insert(empty.id, empty);
+
+        session
+    }
+
+    fn titles(entries: &[ListEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.title.as_str()).collect()
+    }
+
+    #[test]
+    fn test_counts_and_tokens() {
+        let session = example_session();
+        let entries = list_threads(&session, &ListOptions::default());
+
+        assert_eq!(
+            titles(&entries),
+            vec!["Rust lifetimes", "Soup recipes", "Rust macros"]
+        );
+        assert_eq!(entries[0].messages, 2);
+
+        // "prompt", "a question" and "an answer"
+        assert_eq!(entries[0].tokens, 2 + 3 + 3);
+        assert_eq!(estimate_tokens(""), 0);
+    }
+
+    #[test]
+    fn test_filter_sort_and_limit() {
+        let session = example_session();
+
+        let options = ListOptions {
+            model: Some("GPT-4O".into()),
+            ..Default::default()
+        };
+        let entries = list_threads(&session, &options);
+        assert_eq!(titles(&entries), vec!["Rust lifetimes", "Rust macros"]);
+        assert_eq!(entries[1].index, 3);
+
+        let options = ListOptions {
+            prompt: Some("assistant".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            titles(&list_threads(&session, &options)),
+            vec!["Soup recipes", "Rust macros"]
+        );
+
+        let options = ListOptions {
+            since: NaiveDate::from_ymd_opt(2024, 1, 5),
+            until: NaiveDate::from_ymd_opt(2024, 1, 10),
+            ..Default::default()
+        };
+        assert_eq!(
+            titles(&list_threads(&session, &options)),
+            vec!["Soup recipes", "Rust macros"]
+        );
+
+        let options = ListOptions {
+            sort: ListSort::Updated,
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            titles(&list_threads(&session, &options)),
+            vec!["Rust macros", "Rust lifetimes"]
+        );
+    }
+
+    #[test]
+    fn test_json_output() {
+        let session = example_session();
+        let entries = list_threads(&session, &ListOptions::default());
+
+        let json = serde_json::to_value(&entries[..1]).unwrap();
+        assert_eq!(json[0]["title"], "Rust lifetimes");
+        assert_eq!(json[0]["model"], "gpt-4o");
+        assert_eq!(json[0]["prompt"], "Programmer");
+        assert_eq!(json[0]["messages"], 2);
+        assert!(json[0].get("forked_from_index").is_none());
+        assert!(json[0].get("short_id").is_none());
+
+        let line = entries[0].to_line();
+        assert!(line.starts_with(&format!(
+            "(1) {} ",
+            &entries[0].id.as_simple().to_string()[..8]
+        )));
+    }
+}
@@ -1,26 +1,63 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
-#[derive(
-    Clone,
-    Copy,
-    Debug,
-    Default,
-    PartialEq,
-    Eq,
-    Hash,
-    strum_macros::EnumVariantNames,
-    Serialize,
-    Deserialize,
-)]
-#[repr(u8)]
+/// A chat model, identified by the name the API knows it by.
+/// Well-known models carry extra metadata, but any model name can be used.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum LlmModel {
     #[default]
-    #[serde(rename = "gpt-4")]
     GPT4,
-
-    #[serde(rename = "gpt-3.5-turbo")]
+    GPT4Turbo,
+    GPT4o,
+    GPT4oMini,
     GPT35Turbo,
+    O1,
+    O1Mini,
+
+    /// Any model not listed above, such as a local model or one released after this was written
+    Other(String),
+}
+
+/// The API a model is served through
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    OpenAi,
+    Anthropic,
+    Ollama,
+}
+
+impl ProviderKind {
+    pub fn default_base_url(&self) -> &'static str {
+        match self {
+            Self::OpenAi => "https://api.openai.com/v1",
+            Self::Anthropic => "https://api.anthropic.com/v1",
+            Self::Ollama => "http://localhost:11434",
+        }
+    }
+
+    /// The environment variable the API key is read from, if this provider needs one
+    pub fn default_api_key_var(&self) -> Option<&'static str> {
+        match self {
+            Self::OpenAi => Some("OPENAI_API_KEY"),
+            Self::Anthropic => Some("ANTHROPIC_API_KEY"),
+            Self::Ollama => None,
+        }
+    }
+}
+
+impl Display for ProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            Self::OpenAi => "openai",
+            Self::Anthropic => "anthropic",
+            Self::Ollama => "ollama",
+        };
+
+        write!(f, "{}", label)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
@@ -29,6 +66,12 @@ pub struct PromptSetting {
     pub prompt: String,
     pub model: LlmModel,
     pub color: Option<String>,
+
+    /// Overrides the global `provider` for threads using this prompt
+    pub provider: Option<ProviderKind>,
+
+    /// Overrides the global `base_url` for threads using this prompt
+    pub base_url: Option<String>,
 }
 
 impl PromptSetting {
@@ -44,26 +87,59 @@ impl Default for PromptSetting {
             prompt: "You are a helpful assistant".into(),
             color: None,
             model: LlmModel::default(),
+            provider: None,
+            base_url: None,
         }
     }
 }
 impl LlmModel {
+    /// The size of this model's context window, in tokens.
+    /// Unknown models use the `default_context_size` from the config.
     pub fn max_context(&self) -> usize {
         match self {
             Self::GPT35Turbo => 4_096,
             Self::GPT4 => 8_192,
+            Self::GPT4Turbo | Self::GPT4o | Self::GPT4oMini | Self::O1Mini => 128_000,
+            Self::O1 => 200_000,
+            Self::Other(_) => crate::config::CONFIG.default_context_size(),
         }
     }
-}
 
-impl Display for LlmModel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let model_label = match self {
+    pub fn label(&self) -> &str {
+        match self {
             Self::GPT4 => "gpt-4",
+            Self::GPT4Turbo => "gpt-4-turbo",
+            Self::GPT4o => "gpt-4o",
+            Self::GPT4oMini => "gpt-4o-mini",
             Self::GPT35Turbo => "gpt-3.5-turbo",
-        };
+            Self::O1 => "o1",
+            Self::O1Mini => "o1-mini",
+            Self::Other(label) => label,
+        }
+    }
+
+    pub fn from_label(label: impl AsRef<str>) -> Self {
+        match label.as_ref().trim() {
+            "gpt-4" => Self::GPT4,
+            "gpt-4-turbo" => Self::GPT4Turbo,
+            "gpt-4o" => Self::GPT4o,
+            "gpt-4o-mini" => Self::GPT4oMini,
+            "gpt-3.5-turbo" => Self::GPT35Turbo,
+            "o1" => Self::O1,
+            "o1-mini" => Self::O1Mini,
+            other => Self::Other(other.into()),
+        }
+    }
+
+    /// Whether this model has known metadata, rather than falling back to defaults
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Self::Other(_))
+    }
+}
 
-        write!(f, "{}", model_label)
+impl Display for LlmModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label())
     }
 }
 
@@ -73,12 +149,40 @@ impl From<LlmModel> for String {
     }
 }
 
-impl LlmModel {
-    pub fn from_label(label: impl AsRef<str>) -> Option<Self> {
-        match label.as_ref() {
-            "gpt-4" => Some(Self::GPT4),
-            "gpt-3.5-turbo" => Some(Self::GPT35Turbo),
-            _ => None,
+impl From<String> for LlmModel {
+    fn from(val: String) -> Self {
+        Self::from_label(val)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_model_label_round_trip() {
+        for label in [
+            "gpt-4",
+            "gpt-4o",
+            "o1",
+            "llama3:8b",
+            "claude-3-5-sonnet-latest",
+        ] {
+            let model = LlmModel::from_label(label);
+            assert_eq!(model.to_string(), label);
         }
+
+        assert!(LlmModel::from_label("gpt-4o").is_known());
+        assert_eq!(
+            LlmModel::from_label("mistral"),
+            LlmModel::Other("mistral".into())
+        );
+    }
+
+    #[test]
+    fn test_model_serde() {
+        let model: LlmModel = serde_json::from_str("\"some-local-model\"").unwrap();
+        assert_eq!(model, LlmModel::Other("some-local-model".into()));
+        assert_eq!(serde_json::to_string(&LlmModel::GPT4).unwrap(), "\"gpt-4\"");
     }
 }
@@ -2,15 +2,6 @@ use gptui::cli::run_cli;
 use gptui::session::Session;
 
 fn main() -> gptui::Result<()> {
-    #[cfg(feature = "debug-dump")]
-    {
-        let mut session = Session::new()?;
-        session.load_threads()?;
-        session.dump_all();
-        println!("Dumped session json");
-        std::process::exit(0);
-    }
-
     #[cfg(debug_assertions)]
     {
         if let Some(arg) = std::env::args_os()
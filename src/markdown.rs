@@ -0,0 +1,761 @@
+use pulldown_cmark::{Alignment, Event, HeadingLevel, Options, Parser, Tag};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Bullets for unordered lists, by how deeply the list is nested
+const BULLETS: [&str; 3] = ["• ", "◦ ", "▪ "];
+
+const TABLE_COLUMN_SEPARATOR: &str = " │ ";
+
+/// Narrowest a line of text is allowed to get, however deeply it's indented
+const MIN_TEXT_WIDTH: usize = 10;
+
+/// A run of text in a single style
+type StyledText = (String, Style);
+
+fn heading_style(level: HeadingLevel) -> Style {
+    let style = Style::default().add_modifier(Modifier::BOLD);
+
+    match level {
+        HeadingLevel::H1 => style.fg(Color::Magenta).add_modifier(Modifier::UNDERLINED),
+        HeadingLevel::H2 => style.fg(Color::Cyan),
+        _ => style,
+    }
+}
+
+fn code_style() -> Style {
+    Style::default().fg(Color::Yellow)
+}
+
+fn faint_style() -> Style {
+    Style::default().fg(Color::DarkGray)
+}
+
+fn styled_width(runs: &[StyledText]) -> usize {
+    runs.iter().map(|(text, _)| text.width()).sum()
+}
+
+/// Add text to a line, merging it into the last run if the style is the same
+fn push_run(line: &mut Vec<StyledText>, text: &str, style: Style) {
+    match line.last_mut() {
+        Some((last, last_style)) if *last_style == style => last.push_str(text),
+        _ => line.push((text.to_string(), style)),
+    }
+}
+
+fn trim_end_runs(line: &mut Vec<StyledText>) {
+    while let Some((text, _)) = line.last_mut() {
+        let trimmed_len = text.trim_end().len();
+        text.truncate(trimmed_len);
+
+        if text.is_empty() {
+            line.pop();
+        } else {
+            break;
+        }
+    }
+}
+
+/// Split text into words, runs of whitespace, and newlines, keeping all of them
+fn split_words(text: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut last_class = None;
+
+    for (i, c) in text.char_indices() {
+        let class = match c {
+            '\n' => 0,
+            c if c.is_whitespace() => 1,
+            _ => 2,
+        };
+
+        // every newline is a piece of its own
+        if last_class.is_some() && (last_class != Some(class) || class == 0) {
+            pieces.push(&text[start..i]);
+            start = i;
+        }
+
+        last_class = Some(class);
+    }
+
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+
+    pieces
+}
+
+/// Lay out styled text in lines no wider than `width`, breaking between words where possible
+fn wrap_styled(runs: &[StyledText], width: usize) -> Vec<Vec<StyledText>> {
+    let width = width.max(1);
+    let mut lines: Vec<Vec<StyledText>> = vec![Vec::new()];
+    let mut line_width = 0;
+
+    for (text, style) in runs {
+        for piece in split_words(text) {
+            if piece == "\n" {
+                lines.push(Vec::new());
+                line_width = 0;
+                continue;
+            }
+
+            let piece_width = piece.width();
+
+            if piece.trim().is_empty() {
+                // spaces at the start of a wrapped line are dropped
+                if line_width > 0 && line_width + piece_width <= width {
+                    push_run(lines.last_mut().unwrap(), piece, *style);
+                    line_width += piece_width;
+                }
+                continue;
+            }
+
+            if line_width > 0 && line_width + piece_width > width {
+                trim_end_runs(lines.last_mut().unwrap());
+                lines.push(Vec::new());
+                line_width = 0;
+            }
+
+            // words too long for a line of their own are broken wherever they need to be
+            for c in piece.chars() {
+                let char_width = c.width().unwrap_or(0);
+
+                if line_width > 0 && line_width + char_width > width {
+                    lines.push(Vec::new());
+                    line_width = 0;
+                }
+
+                push_run(
+                    lines.last_mut().unwrap(),
+                    c.encode_utf8(&mut [0; 4]),
+                    *style,
+                );
+                line_width += char_width;
+            }
+        }
+    }
+
+    for line in lines.iter_mut() {
+        trim_end_runs(line);
+    }
+
+    lines
+}
+
+/// Cut styled text down to `width` columns, marking any cut with an ellipsis,
+/// then pad it out to exactly `width` according to the alignment
+fn fit_cell(cell: &[StyledText], width: usize, alignment: Alignment) -> Vec<StyledText> {
+    let mut fitted = Vec::new();
+
+    if styled_width(cell) <= width {
+        fitted.extend(cell.iter().cloned());
+    } else {
+        let mut used = 0;
+
+        'runs: for (text, style) in cell {
+            for c in text.chars() {
+                let char_width = c.width().unwrap_or(0);
+
+                if used + char_width + 1 > width {
+                    break 'runs;
+                }
+
+                push_run(&mut fitted, c.encode_utf8(&mut [0; 4]), *style);
+                used += char_width;
+            }
+        }
+
+        fitted.push(("…".to_string(), faint_style()));
+    }
+
+    let padding = width.saturating_sub(styled_width(&fitted));
+
+    let (left, right) = match alignment {
+        Alignment::Right => (padding, 0),
+        Alignment::Center => (padding / 2, padding - padding / 2),
+        _ => (0, padding),
+    };
+
+    if left > 0 {
+        fitted.insert(0, (" ".repeat(left), Style::default()));
+    }
+
+    if right > 0 {
+        fitted.push((" ".repeat(right), Style::default()));
+    }
+
+    fitted
+}
+
+struct ListLevel {
+    /// The number of the next item, for ordered lists
+    next_number: Option<u64>,
+
+    /// How far the text of this list's items is indented past the marker
+    indent: usize,
+}
+
+struct TableBuilder {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<Vec<StyledText>>>,
+}
+
+struct Renderer {
+    width: usize,
+    lines: Vec<Line<'static>>,
+
+    /// Text of the paragraph, heading or list item being built
+    inline: Vec<StyledText>,
+
+    styles: Vec<Style>,
+    lists: Vec<ListLevel>,
+
+    /// The marker of a list item, waiting for the item's first line
+    item_marker: Option<String>,
+
+    quote_depth: usize,
+
+    /// Whether a blank line is due before the next block
+    blank_pending: bool,
+
+    link_urls: Vec<String>,
+    table: Option<TableBuilder>,
+
+    /// Lines of an indented code block, or a fenced one that wasn't taken out to be highlighted
+    code_block: Option<String>,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            lines: Vec::new(),
+            inline: Vec::new(),
+            styles: vec![Style::default()],
+            lists: Vec::new(),
+            item_marker: None,
+            quote_depth: 0,
+            blank_pending: false,
+            link_urls: Vec::new(),
+            table: None,
+            code_block: None,
+        }
+    }
+
+    fn style(&self) -> Style {
+        self.styles.last().copied().unwrap_or_default()
+    }
+
+    fn push_style(&mut self, style: Style) {
+        self.styles.push(self.style().patch(style));
+    }
+
+    fn pop_style(&mut self) {
+        if self.styles.len() > 1 {
+            self.styles.pop();
+        }
+    }
+
+    fn push_text(&mut self, text: &str, style: Style) {
+        if let Some(code_block) = self.code_block.as_mut() {
+            code_block.push_str(text);
+            return;
+        }
+
+        let target = match self.table.as_mut() {
+            Some(table) => match table.rows.last_mut().and_then(|row| row.last_mut()) {
+                Some(cell) => cell,
+                None => return,
+            },
+            None => &mut self.inline,
+        };
+
+        push_run(target, text, style);
+    }
+
+    /// What comes before each line: a bar for each block quote, then the indent of each list.
+    /// The first line of a list item gets its marker instead of the last indent.
+    fn prefixes(&mut self) -> (Vec<StyledText>, Vec<StyledText>) {
+        let quote = ("│ ".repeat(self.quote_depth), faint_style());
+        let indent: usize = self.lists.iter().map(|l| l.indent).sum();
+
+        let rest = vec![quote.clone(), (" ".repeat(indent), Style::default())];
+
+        let first = match self.item_marker.take() {
+            Some(marker) => {
+                let outer_indent = indent - self.lists.last().map(|l| l.indent).unwrap_or(0);
+                vec![
+                    quote,
+                    (" ".repeat(outer_indent), Style::default()),
+                    (marker, Style::default().fg(Color::Cyan)),
+                ]
+            }
+            None => rest.clone(),
+        };
+
+        (first, rest)
+    }
+
+    fn push_line(&mut self, runs: Vec<StyledText>) {
+        let spans = runs
+            .into_iter()
+            .filter(|(text, _)| !text.is_empty())
+            .map(|(text, style)| Span::styled(text, style))
+            .collect::<Vec<_>>();
+
+        self.lines.push(Line::from(spans));
+    }
+
+    /// Lay out the text built up so far
+    fn flush(&mut self) {
+        if self.inline.iter().all(|(text, _)| text.trim().is_empty()) {
+            self.inline.clear();
+            return;
+        }
+
+        let runs = std::mem::take(&mut self.inline);
+        let (first, rest) = self.prefixes();
+
+        let text_width = self
+            .width
+            .saturating_sub(styled_width(&rest))
+            .max(MIN_TEXT_WIDTH);
+
+        for (i, line) in wrap_styled(&runs, text_width).into_iter().enumerate() {
+            let prefix = if i == 0 { first.clone() } else { rest.clone() };
+            self.push_line(prefix.into_iter().chain(line).collect());
+        }
+    }
+
+    /// Separate the block about to start from the one before it
+    fn start_block(&mut self) {
+        self.flush();
+
+        if self.blank_pending && !self.lines.is_empty() {
+            let (_, rest) = self.prefixes_without_marker();
+            self.push_line(rest);
+        }
+
+        self.blank_pending = false;
+    }
+
+    fn prefixes_without_marker(&mut self) -> (Vec<StyledText>, Vec<StyledText>) {
+        let marker = self.item_marker.take();
+        let prefixes = self.prefixes();
+        self.item_marker = marker;
+        prefixes
+    }
+
+    fn end_block(&mut self) {
+        self.flush();
+        self.blank_pending = true;
+    }
+
+    fn render_code_block(&mut self, code: String) {
+        let (_, rest) = self.prefixes();
+        let code_width = self
+            .width
+            .saturating_sub(styled_width(&rest))
+            .max(MIN_TEXT_WIDTH);
+
+        for line in code.trim_end_matches('\n').lines() {
+            // code keeps its spacing, so it's cut into pieces rather than wrapped between words
+            let mut piece = String::new();
+            let mut piece_width = 0;
+
+            for c in line.chars() {
+                let char_width = c.width().unwrap_or(0);
+
+                if piece_width + char_width > code_width {
+                    let mut runs = rest.clone();
+                    runs.push((std::mem::take(&mut piece), code_style()));
+                    self.push_line(runs);
+                    piece_width = 0;
+                }
+
+                piece.push(c);
+                piece_width += char_width;
+            }
+
+            let mut runs = rest.clone();
+            runs.push((piece, code_style()));
+            self.push_line(runs);
+        }
+    }
+
+    fn render_table(&mut self, table: TableBuilder) {
+        let column_count = table.rows.iter().map(|r| r.len()).max().unwrap_or(0);
+
+        if column_count == 0 {
+            return;
+        }
+
+        let mut widths = vec![0usize; column_count];
+
+        for row in table.rows.iter() {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(styled_width(cell));
+            }
+        }
+
+        let (_, prefix) = self.prefixes();
+
+        let available = self
+            .width
+            .saturating_sub(styled_width(&prefix))
+            .saturating_sub(TABLE_COLUMN_SEPARATOR.width() * (column_count - 1));
+
+        // narrow the widest columns until the table fits
+        while widths.iter().sum::<usize>() > available {
+            let Some(widest) = widths.iter_mut().filter(|w| **w > 1).max() else {
+                break;
+            };
+
+            *widest -= 1;
+        }
+
+        let empty_cell = Vec::new();
+
+        for (row_index, row) in table.rows.iter().enumerate() {
+            let mut runs = prefix.clone();
+
+            for (i, width) in widths.iter().enumerate() {
+                let cell = row.get(i).unwrap_or(&empty_cell);
+                let alignment = table.alignments.get(i).copied().unwrap_or(Alignment::None);
+
+                let mut fitted = fit_cell(cell, *width, alignment);
+
+                // the first row is the header
+                if row_index == 0 {
+                    for (_, style) in fitted.iter_mut() {
+                        *style = style.add_modifier(Modifier::BOLD);
+                    }
+                }
+
+                runs.extend(fitted);
+
+                if i + 1 < column_count {
+                    runs.push((TABLE_COLUMN_SEPARATOR.to_string(), faint_style()));
+                }
+            }
+
+            trim_end_runs(&mut runs);
+            self.push_line(runs);
+
+            if row_index == 0 {
+                let rule = widths.iter().map(|w| "─".repeat(*w)).collect::<Vec<_>>();
+
+                let mut runs = prefix.clone();
+                runs.push((rule.join("─┼─"), faint_style()));
+                self.push_line(runs);
+            }
+        }
+    }
+
+    fn start_tag(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+
+            Tag::Heading(level, _, _) => {
+                self.start_block();
+                self.push_style(heading_style(level));
+            }
+
+            Tag::BlockQuote => {
+                self.start_block();
+                self.quote_depth += 1;
+            }
+
+            Tag::CodeBlock(_) => {
+                self.start_block();
+                self.code_block = Some(String::new());
+            }
+
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                } else {
+                    // the text of the item this list is nested in
+                    self.flush();
+                }
+
+                self.lists.push(ListLevel {
+                    next_number: start,
+                    indent: 0,
+                });
+            }
+
+            Tag::Item => {
+                self.flush();
+
+                let depth = self.lists.len().saturating_sub(1);
+
+                if let Some(level) = self.lists.last_mut() {
+                    let marker = match level.next_number.as_mut() {
+                        Some(n) => {
+                            *n += 1;
+                            format!("{}. ", *n - 1)
+                        }
+                        None => BULLETS[depth % BULLETS.len()].to_string(),
+                    };
+
+                    level.indent = marker.width();
+                    self.item_marker = Some(marker);
+                }
+            }
+
+            Tag::Table(alignments) => {
+                self.start_block();
+                self.table = Some(TableBuilder {
+                    alignments,
+                    rows: Vec::new(),
+                });
+            }
+
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = self.table.as_mut() {
+                    table.rows.push(Vec::new());
+                }
+            }
+
+            Tag::TableCell => {
+                if let Some(row) = self.table.as_mut().and_then(|t| t.rows.last_mut()) {
+                    row.push(Vec::new());
+                }
+            }
+
+            Tag::Emphasis => self.push_style(Style::default().add_modifier(Modifier::ITALIC)),
+            Tag::Strong => self.push_style(Style::default().add_modifier(Modifier::BOLD)),
+            Tag::Strikethrough => {
+                self.push_style(Style::default().add_modifier(Modifier::CROSSED_OUT))
+            }
+
+            Tag::Link(_, url, _) | Tag::Image(_, url, _) => {
+                self.push_style(
+                    Style::default()
+                        .fg(Color::Blue)
+                        .add_modifier(Modifier::UNDERLINED),
+                );
+                self.link_urls.push(url.to_string());
+            }
+
+            Tag::FootnoteDefinition(label) => {
+                self.start_block();
+                self.push_text(&format!("[{}]: ", label), faint_style());
+            }
+        }
+    }
+
+    fn end_tag(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::FootnoteDefinition(_) => self.end_block(),
+
+            Tag::Heading(..) => {
+                self.end_block();
+                self.pop_style();
+            }
+
+            Tag::BlockQuote => {
+                self.end_block();
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+            }
+
+            Tag::CodeBlock(_) => {
+                if let Some(code) = self.code_block.take() {
+                    self.render_code_block(code);
+                }
+                self.blank_pending = true;
+            }
+
+            Tag::List(_) => {
+                self.flush();
+                self.lists.pop();
+
+                if self.lists.is_empty() {
+                    self.blank_pending = true;
+                }
+            }
+
+            Tag::Item => self.flush(),
+
+            Tag::Table(_) => {
+                if let Some(table) = self.table.take() {
+                    self.render_table(table);
+                }
+                self.blank_pending = true;
+            }
+
+            Tag::TableHead | Tag::TableRow | Tag::TableCell => (),
+
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough => self.pop_style(),
+
+            Tag::Link(..) | Tag::Image(..) => {
+                self.pop_style();
+
+                // show where a link goes, unless its text already does
+                if let Some(url) = self.link_urls.pop() {
+                    let shows_url = self
+                        .inline
+                        .last()
+                        .is_some_and(|(text, _)| text.ends_with(&url));
+
+                    if !url.is_empty() && !shows_url {
+                        self.push_text(&format!(" ({})", url), faint_style());
+                    }
+                }
+            }
+        }
+    }
+
+    fn render(mut self, text: &str) -> Vec<Line<'static>> {
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS
+            | Options::ENABLE_FOOTNOTES;
+
+        for event in Parser::new_ext(text, options) {
+            match event {
+                Event::Start(tag) => self.start_tag(tag),
+                Event::End(tag) => self.end_tag(tag),
+                Event::Text(text) | Event::Html(text) => self.push_text(&text, self.style()),
+                Event::Code(code) => self.push_text(&code, self.style().patch(code_style())),
+                Event::SoftBreak => self.push_text(" ", self.style()),
+                Event::HardBreak => self.push_text("\n", self.style()),
+                Event::Rule => {
+                    self.start_block();
+                    let (_, prefix) = self.prefixes();
+                    let rule_width = self.width.saturating_sub(styled_width(&prefix));
+
+                    let mut runs = prefix;
+                    runs.push(("─".repeat(rule_width), faint_style()));
+                    self.push_line(runs);
+                    self.blank_pending = true;
+                }
+                Event::TaskListMarker(checked) => {
+                    let marker = if checked { "[x] " } else { "[ ] " };
+                    self.push_text(marker, Style::default().fg(Color::Cyan));
+                }
+                Event::FootnoteReference(label) => {
+                    self.push_text(&format!("[{}]", label), faint_style())
+                }
+            }
+        }
+
+        self.flush();
+        self.lines
+    }
+}
+
+/// Lay out Markdown text as styled lines no wider than `width`.
+/// Lists are indented, block quotes get a bar down the side, and tables are lined up in columns.
+pub fn render_markdown(text: &str, width: usize) -> Vec<Line<'static>> {
+    Renderer::new(width).render(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plain_lines(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect())
+            .collect()
+    }
+
+    fn span_with<'a>(lines: &'a [Line<'static>], text: &str) -> &'a Span<'static> {
+        lines
+            .iter()
+            .flat_map(|l| l.spans.iter())
+            .find(|s| s.content.contains(text))
+            .expect("Text was rendered")
+    }
+
+    #[test]
+    fn test_inline_styles() {
+        let lines = render_markdown(
+            "# Title\n\nSome **bold**, *italic* and `code`, with a [link](https://example.com).",
+            80,
+        );
+
+        assert_eq!(
+            plain_lines(&lines),
+            vec![
+                "Title",
+                "",
+                "Some bold, italic and code, with a link (https://example.com)."
+            ]
+        );
+
+        assert!(span_with(&lines, "Title")
+            .style
+            .add_modifier
+            .contains(Modifier::BOLD));
+        assert!(span_with(&lines, "bold")
+            .style
+            .add_modifier
+            .contains(Modifier::BOLD));
+        assert!(span_with(&lines, "italic")
+            .style
+            .add_modifier
+            .contains(Modifier::ITALIC));
+        assert_eq!(span_with(&lines, "code").style.fg, Some(Color::Yellow));
+        assert_eq!(span_with(&lines, "link").style.fg, Some(Color::Blue));
+    }
+
+    #[test]
+    fn test_lists_are_indented() {
+        let lines = render_markdown(
+            "1. first item, which is long enough to wrap\n2. second\n   - nested\n\n- [x] done",
+            24,
+        );
+
+        assert_eq!(
+            plain_lines(&lines),
+            vec![
+                "1. first item, which is",
+                "   long enough to wrap",
+                "2. second",
+                "   ◦ nested",
+                "",
+                "• [x] done",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_quotes() {
+        let lines = render_markdown("> quoted\n> text\n\nafter", 80);
+        assert_eq!(plain_lines(&lines), vec!["│ quoted text", "", "after"]);
+    }
+
+    #[test]
+    fn test_tables_are_aligned() {
+        let lines = render_markdown(
+            "| Name | Count |\n|:-----|------:|\n| apples | 3 |\n| kiwis | 12 |",
+            80,
+        );
+
+        assert_eq!(
+            plain_lines(&lines),
+            vec![
+                "Name   │ Count",
+                "───────┼──────",
+                "apples │     3",
+                "kiwis  │    12",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tables_are_narrowed_to_fit() {
+        let lines = render_markdown("| a | b |\n|---|---|\n| 123456789 | x |", 10);
+
+        for line in plain_lines(&lines) {
+            assert!(line.width() <= 10, "'{}' is too wide", line);
+        }
+
+        assert!(plain_lines(&lines)[2].contains('…'));
+    }
+}
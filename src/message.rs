@@ -1,17 +1,22 @@
 use ansi_to_tui::IntoText;
 use anyhow::format_err;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SubsecRound, Utc};
 
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, StyledGrapheme, Text};
 use serde::{Deserialize, Serialize};
 use textwrap::wrap;
 
+use crate::highlight::highlighting;
+use crate::markdown::render_markdown;
+
 use std::time::{SystemTime, UNIX_EPOCH};
 use syntect::easy::HighlightLines;
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
 use syntect::parsing::SyntaxReference;
 
 use itertools::Itertools;
+use uuid::Uuid;
 
 lazy_static::lazy_static! {
 
@@ -20,16 +25,8 @@ lazy_static::lazy_static! {
         .build()
         .expect("Regex failed to compile");
 
-    static ref SYNTAX_SET: syntect::parsing::SyntaxSet =  syntect::parsing::SyntaxSet::load_defaults_nonewlines();
-
-
-    static ref THEME_SET: syntect::highlighting::ThemeSet = syntect::highlighting::ThemeSet::load_defaults();
-
-
 }
 
-const DEFAULT_THEME: &str = "base16-eighties.dark";
-
 #[allow(unused)]
 fn timestamp() -> f64 {
     SystemTime::now()
@@ -38,6 +35,26 @@ fn timestamp() -> f64 {
         .as_secs_f64()
 }
 
+/// Convert seconds since the epoch, as stored in the database, back to a timestamp
+pub fn timestamp_from_epoch(timestamp_epoch: f64) -> DateTime<Utc> {
+    // rounding to the nearest microsecond undoes any error from storing it as a float
+    let timestamp_micros = (timestamp_epoch * 1_000_000f64).round() as i64;
+
+    DateTime::from_timestamp(
+        timestamp_micros.div_euclid(1_000_000),
+        (timestamp_micros.rem_euclid(1_000_000) * 1_000) as u32,
+    )
+    .expect("Epoch time was valid")
+}
+
+/// A timestamp in local time, to the minute, as shown next to threads and messages
+pub fn local_time_fmt(timestamp: DateTime<Utc>) -> String {
+    timestamp
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum Role {
@@ -48,7 +65,7 @@ pub enum Role {
 }
 
 impl Role {
-    pub fn tui_display_header(&self) -> Span {
+    pub fn tui_display_header(&self) -> Span<'_> {
         match self {
             Role::User => Span::styled(
                 "User",
@@ -95,9 +112,20 @@ pub struct Message {
     pub role: Role,
     pub content: String,
 
+    #[serde(skip)]
+    pub id: Uuid,
+
+    /// The message this one follows on from, None for the first message in a thread
+    #[serde(skip)]
+    pub parent_id: Option<Uuid>,
+
     #[serde(skip)]
     pub timestamp: DateTime<Utc>,
 
+    /// Set if the reply was cancelled before it finished
+    #[serde(skip)]
+    pub truncated: bool,
+
     #[serde(skip)]
     code_blocks: Vec<CodeBlock>,
 
@@ -105,21 +133,54 @@ pub struct Message {
     non_code_content: String,
 }
 
-const BLOCK_MARKER: &str = "```__<BLOCK>__```";
+pub(crate) const BLOCK_MARKER: &str = "```__<BLOCK>__```";
+
+/// Part of a message: text between code blocks, or a code block
+#[derive(Debug, Clone, Copy)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Code(&'a CodeBlock),
+}
+
 impl Message {
     pub fn code_blocks(&self) -> Vec<&CodeBlock> {
         self.code_blocks.iter().collect()
     }
 
-    pub fn non_code_content(&self) -> &str {
-        &self.non_code_content
+    /// The message's text and code blocks in order, leaving out blank text between them
+    pub fn segments(&self) -> Vec<Segment<'_>> {
+        let mut segments = Vec::new();
+        let mut blocks = self.code_blocks.iter();
+
+        // each code block was replaced by a marker, so text and blocks alternate
+        for (i, text) in self.non_code_content.split(BLOCK_MARKER).enumerate() {
+            if i > 0 {
+                if let Some(block) = blocks.next() {
+                    segments.push(Segment::Code(block));
+                }
+            }
+
+            if !text.trim().is_empty() {
+                segments.push(Segment::Text(text));
+            }
+        }
+
+        segments
+    }
+
+    /// A note to show after a reply that was cancelled before it finished, if this is one
+    pub fn truncation_note(&self) -> Option<&'static str> {
+        self.truncated
+            .then_some("(This reply was cancelled before it finished)")
     }
 
     pub fn new(role: Role, content: &str, timestamp: DateTime<Utc>) -> Self {
         let mut new_msg = Self {
             role,
             content: content.into(),
-            timestamp,
+            // the database keeps microseconds, so anything finer would be lost on a round trip
+            timestamp: timestamp.trunc_subsecs(6),
+            id: Uuid::new_v4(),
             ..Default::default()
         };
 
@@ -146,20 +207,12 @@ impl Message {
     }
 
     pub fn new_from_db(role: Role, content: String, timestamp_epoch: f64) -> Self {
-        let timestamp_secs = f64::floor(timestamp_epoch) as i64;
-        let timestamp_nanos = f64::fract(timestamp_epoch) * 1_000_000f64;
-
-        let timestamp = DateTime::from_timestamp(timestamp_secs, timestamp_nanos.floor() as u32)
-            .expect("Epoch time was valid");
-
-        Self::new(role, &content, timestamp)
+        Self::new(role, &content, timestamp_from_epoch(timestamp_epoch))
     }
 
+    /// Seconds since the epoch, to the microsecond
     pub fn timestamp_epoch(&self) -> f64 {
-        let subsecs = self.timestamp.timestamp_subsec_millis() as f64;
-        let secs = self.timestamp.timestamp() as f64;
-
-        secs + (subsecs / 1000f64)
+        self.timestamp.timestamp_micros() as f64 / 1_000_000f64
     }
 
     pub fn timestamp_millis(&self) -> i64 {
@@ -180,13 +233,30 @@ impl Message {
     /// `index` is the value to start numbering the block annotations from
     pub fn formatted_content<'a>(&'a self, index: &mut usize, line_width: u16) -> Text<'a> {
         let mut formatted_lines: Vec<Line> = Vec::new();
+
+        // replies are written in Markdown, so they're rendered as such
+        if self.is_assistant() {
+            for segment in self.segments() {
+                match segment {
+                    Segment::Code(block) => {
+                        formatted_lines.extend(block.highlighted_text(*index, line_width).lines);
+                        *index += 1;
+                    }
+                    Segment::Text(text) => {
+                        formatted_lines.extend(render_markdown(text, line_width as usize))
+                    }
+                }
+            }
+
+            return Text::from(formatted_lines);
+        }
+
         let mut block_index = 0usize;
 
         for msg_line in wrap(&self.non_code_content, line_width as usize) {
             if msg_line.trim() == BLOCK_MARKER {
                 if let Some(block) = self.code_blocks.get(block_index) {
-                    formatted_lines
-                        .extend(block.highlighted_text(*index, line_width).lines.into_iter());
+                    formatted_lines.extend(block.highlighted_text(*index, line_width).lines);
                     block_index += 1;
                     *index += 1;
                 }
@@ -261,14 +331,15 @@ impl CodeBlock {
     }
 
     fn update_lines(&mut self) {
-        let mut hl = HighlightLines::new(self.syntax(), &THEME_SET.themes[DEFAULT_THEME]);
+        let highlighting = highlighting();
+        let mut hl = HighlightLines::new(self.syntax(), highlighting.theme());
 
         let term_lines = self
             .content
             .lines()
             .map(|line| {
                 let ranges: Vec<(syntect::highlighting::Style, &str)> =
-                    hl.highlight_line(line, &SYNTAX_SET).unwrap();
+                    hl.highlight_line(line, highlighting.syntaxes()).unwrap();
 
                 syntect::util::as_24_bit_terminal_escaped(&ranges[..], true)
             })
@@ -285,9 +356,7 @@ impl CodeBlock {
     }
 
     pub fn highlighted_text(&self, index: usize, line_width: u16) -> Text<'_> {
-        let _hl = HighlightLines::new(self.syntax(), &THEME_SET.themes[DEFAULT_THEME]);
-
-        let bg_color = THEME_SET.themes[DEFAULT_THEME].settings.background.map(
+        let bg_color = highlighting().theme().settings.background.map(
             |syntect::highlighting::Color { r, g, b, .. }| ratatui::style::Color::Rgb(r, g, b),
         );
 
@@ -326,17 +395,65 @@ impl CodeBlock {
         Text::from(formatted_lines)
     }
 
-    fn syntax(&self) -> &SyntaxReference {
+    /// The code as an HTML `pre` block, highlighted with the same theme as in the terminal
+    pub fn highlighted_html(&self) -> crate::Result<String> {
+        let highlighting = highlighting();
+        let theme = highlighting.theme();
+        let mut hl = HighlightLines::new(self.syntax(), theme);
+
+        let html_lines = self
+            .content
+            .lines()
+            .map(|line| {
+                let ranges = hl.highlight_line(line, highlighting.syntaxes())?;
+                styled_line_to_highlighted_html(&ranges[..], IncludeBackground::No)
+            })
+            .collect::<Result<Vec<_>, syntect::Error>>()
+            .map_err(|e| crate::Error::Other(e.into()))?;
+
+        let css_color =
+            |c: syntect::highlighting::Color| format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b);
+
+        let style = [
+            theme
+                .settings
+                .background
+                .map(|c| format!("background-color:{};", css_color(c))),
+            theme
+                .settings
+                .foreground
+                .map(|c| format!("color:{};", css_color(c))),
+        ]
+        .into_iter()
+        .flatten()
+        .join("");
+
+        Ok(format!(
+            "<pre class=\"code\" style=\"{}\"><code>{}</code></pre>",
+            style,
+            html_lines.join("\n")
+        ))
+    }
+
+    fn syntax(&self) -> &'static SyntaxReference {
+        let syntaxes = highlighting().syntaxes();
+
         self.language
             .as_ref()
-            .and_then(|lang| SYNTAX_SET.find_syntax_by_token(lang))
+            .and_then(|lang| syntaxes.find_syntax_by_token(lang))
             .or_else(|| {
                 self.content
                     .lines()
                     .next()
-                    .and_then(|ln| SYNTAX_SET.find_syntax_by_first_line(ln))
+                    .and_then(|ln| syntaxes.find_syntax_by_first_line(ln))
             })
-            .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text())
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text())
+    }
+
+    /// The code highlighted for a terminal with 24-bit color, one line at a time.
+    /// Each line leaves its colors set, so should be followed by a reset.
+    pub fn lines_24_bit_terminal_escaped(&self) -> &[String] {
+        &self.lines_24_bit_terminal_escaped
     }
 
     pub fn as_raw(&self) -> String {
@@ -0,0 +1,494 @@
+use crate::config::{PromptSetting, CONFIG};
+use crate::message::local_time_fmt;
+use crate::session::{string_preview, Session, Thread};
+use crate::tui::{App, CrosstermTerminal};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use itertools::Itertools;
+use ratatui::{
+    prelude::{Alignment, Constraint, CrosstermBackend, Direction, Layout},
+    style::{Color, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{
+        block::{Position, Title},
+        Block, BorderType, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table,
+        TableState, Wrap,
+    },
+    Frame,
+};
+use std::cmp::Reverse;
+use uuid::Uuid;
+
+/// How many messages of the selected thread are shown in the preview
+const PREVIEW_MESSAGES: usize = 4;
+
+/// Prompts to choose from when starting a thread: the default, then the configured ones by label
+pub(crate) fn available_prompts() -> Vec<PromptSetting> {
+    std::iter::once(PromptSetting::default())
+        .chain(
+            CONFIG
+                .prompts()
+                .into_iter()
+                .sorted_by_key(|p| p.label.to_lowercase())
+                .cloned(),
+        )
+        .collect()
+}
+
+/// Score how well `pattern` matches `text`, if it does at all.
+/// Every character of the pattern must appear in the text, in order, ignoring case.
+/// Runs of consecutive characters and matches at the start of words score higher.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let pattern = pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect_vec();
+
+    if pattern.is_empty() {
+        return Some(0);
+    }
+
+    let text = text.chars().flat_map(char::to_lowercase).collect_vec();
+
+    let mut score = 0i64;
+    let mut pattern_index = 0;
+    let mut last_match: Option<usize> = None;
+
+    for (i, c) in text.iter().enumerate() {
+        if pattern_index == pattern.len() {
+            break;
+        }
+
+        if *c != pattern[pattern_index] {
+            continue;
+        }
+
+        score += 1;
+
+        match last_match {
+            Some(last) if last + 1 == i => score += 5,
+            Some(last) => score -= (i - last - 1).min(5) as i64,
+            None => score -= i.min(10) as i64,
+        }
+
+        if i == 0 || !text[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+
+        last_match = Some(i);
+        pattern_index += 1;
+    }
+
+    (pattern_index == pattern.len()).then_some(score)
+}
+
+enum PickerMode {
+    Browse,
+    ConfirmDelete(Uuid),
+    Rename { id: Uuid, title: String },
+    ChoosePrompt { selected: usize },
+}
+
+/// Full screen list of saved threads, for choosing one to open
+pub struct Picker<'a> {
+    session: &'a mut Session,
+    filter: String,
+    selected: usize,
+    mode: PickerMode,
+    prompts: Vec<PromptSetting>,
+    choice: Option<Uuid>,
+    should_quit: bool,
+    error_text: Option<String>,
+}
+
+impl<'a> Picker<'a> {
+    pub fn new(session: &'a mut Session) -> Self {
+        Self {
+            session,
+            filter: String::new(),
+            selected: 0,
+            mode: PickerMode::Browse,
+            prompts: available_prompts(),
+            choice: None,
+            should_quit: false,
+            error_text: None,
+        }
+    }
+
+    /// Threads matching the filter, best matches first.
+    /// Without a filter, the most recent threads come first.
+    fn matching_threads(&self) -> Vec<&Thread> {
+        self.session
+            .ordered_threads()
+            .into_iter()
+            .rev()
+            .filter_map(|(_, thread)| {
+                fuzzy_score(&self.filter, &thread.display_title()).map(|score| (score, thread))
+            })
+            .sorted_by_key(|(score, _)| Reverse(*score))
+            .map(|(_, thread)| thread)
+            .collect()
+    }
+
+    fn selected_thread(&self) -> Option<&Thread> {
+        self.matching_threads().get(self.selected).copied()
+    }
+
+    fn move_selection(&mut self, forward: bool, len: usize) {
+        self.selected = if forward {
+            (self.selected + 1).min(len.saturating_sub(1))
+        } else {
+            self.selected.saturating_sub(1)
+        };
+    }
+
+    fn update_browse(&mut self, key_event: KeyEvent) -> crate::Result<()> {
+        let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key_event.code {
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.selected = 0;
+            }
+            KeyCode::Esc => self.should_quit = true,
+
+            KeyCode::Up => self.move_selection(false, self.matching_threads().len()),
+            KeyCode::Down => self.move_selection(true, self.matching_threads().len()),
+
+            KeyCode::Enter => self.choice = self.selected_thread().map(|t| t.id),
+
+            // ctrl-n to start a new thread
+            KeyCode::Char('n') if ctrl => self.mode = PickerMode::ChoosePrompt { selected: 0 },
+
+            // ctrl-d to delete the selected thread
+            KeyCode::Char('d') if ctrl => {
+                if let Some(id) = self.selected_thread().map(|t| t.id) {
+                    self.mode = PickerMode::ConfirmDelete(id);
+                }
+            }
+
+            // ctrl-r to rename the selected thread
+            KeyCode::Char('r') if ctrl => {
+                if let Some(thread) = self.selected_thread() {
+                    self.mode = PickerMode::Rename {
+                        id: thread.id,
+                        title: thread.thread_title().unwrap_or_default().into(),
+                    };
+                }
+            }
+
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.filter.push(c);
+                self.selected = 0;
+            }
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, key_event: KeyEvent) -> crate::Result<()> {
+        // ctrl-c to quit from anywhere
+        if key_event.code == KeyCode::Char('c')
+            && key_event.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            self.should_quit = true;
+            return Ok(());
+        }
+
+        match &mut self.mode {
+            PickerMode::Browse => self.update_browse(key_event)?,
+
+            PickerMode::ConfirmDelete(id) => {
+                let id = *id;
+                self.mode = PickerMode::Browse;
+
+                if key_event.code == KeyCode::Char('y') {
+                    self.session.delete_thread(id)?;
+
+                    let remaining = self.matching_threads().len();
+                    self.selected = self.selected.min(remaining.saturating_sub(1));
+                }
+            }
+
+            PickerMode::Rename { id, title } => match key_event.code {
+                KeyCode::Esc => self.mode = PickerMode::Browse,
+                KeyCode::Enter => {
+                    let (id, title) = (*id, title.trim().to_string());
+                    self.mode = PickerMode::Browse;
+
+                    if !title.is_empty() {
+                        self.session.rename_thread(id, &title)?;
+                    }
+                }
+                KeyCode::Backspace => {
+                    title.pop();
+                }
+                KeyCode::Char(c) => title.push(c),
+                _ => (),
+            },
+
+            PickerMode::ChoosePrompt { selected } => match key_event.code {
+                KeyCode::Esc => self.mode = PickerMode::Browse,
+                KeyCode::Up => *selected = selected.saturating_sub(1),
+                KeyCode::Down => {
+                    *selected = (*selected + 1).min(self.prompts.len().saturating_sub(1))
+                }
+                KeyCode::Enter => {
+                    let prompt = self.prompts[*selected].clone();
+                    self.choice = Some(self.session.new_thread(&prompt)?);
+                }
+                _ => (),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn thread_table(&self, threads: &[&Thread]) -> Table<'_> {
+        let rows = threads
+            .iter()
+            .map(|thread| {
+                let date = thread.init_time().map(local_time_fmt).unwrap_or_default();
+
+                Row::new(vec![
+                    Cell::from(date).dark_gray(),
+                    Cell::from(thread.display_title()),
+                    Cell::from(thread.model.to_string()).cyan(),
+                    Cell::from(thread.non_sys_messages().len().to_string()),
+                ])
+            })
+            .collect_vec();
+
+        Table::new(
+            rows,
+            [
+                Constraint::Length(16),
+                Constraint::Min(10),
+                Constraint::Length(20),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(vec!["Started", "Title", "Model", "Messages"]).bold())
+        .highlight_style(Style::default().reversed())
+    }
+
+    fn preview<'t>(&self, thread: Option<&'t Thread>) -> Text<'t> {
+        let Some(thread) = thread else {
+            return Text::default();
+        };
+
+        let mut lines: Vec<Line> = Vec::new();
+
+        for msg in thread.non_sys_messages().into_iter().take(PREVIEW_MESSAGES) {
+            lines.push(thread.message_display_header(msg.role).into());
+            lines.extend(
+                string_preview(&msg.content, 300)
+                    .lines()
+                    .map(|l| Line::from(l.to_string())),
+            );
+            lines.push(Line::default());
+        }
+
+        Text::from(lines)
+    }
+
+    fn prompt_list(&self) -> List<'_> {
+        let items = self
+            .prompts
+            .iter()
+            .map(|p| {
+                ListItem::new(vec![
+                    Line::from(vec![
+                        Span::from(p.label.as_str()).bold(),
+                        Span::from(format!(" ({})", p.model)).cyan(),
+                    ]),
+                    Line::styled(
+                        string_preview(&p.prompt, 80).to_string(),
+                        Style::default().dark_gray(),
+                    ),
+                ])
+            })
+            .collect_vec();
+
+        List::new(items).highlight_style(Style::default().reversed())
+    }
+
+    fn ui(&self, frame: &mut Frame) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(1)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+
+        let threads = self.matching_threads();
+
+        let (input_title, input_text) = match &self.mode {
+            PickerMode::Rename { title, .. } => ("Rename", title.as_str()),
+            _ => ("Filter", self.filter.as_str()),
+        };
+
+        let input = Paragraph::new(Line::from(vec![
+            Span::from("> ").cyan().bold(),
+            Span::from(input_text),
+        ]))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title(input_title),
+        );
+
+        frame.render_widget(input, chunks[0]);
+
+        let body = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(chunks[1]);
+
+        if let PickerMode::ChoosePrompt { selected } = self.mode {
+            let mut list_state = ListState::default().with_selected(Some(selected));
+            frame.render_stateful_widget(
+                self.prompt_list().block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .title("New thread with prompt"),
+                ),
+                body[0],
+                &mut list_state,
+            );
+        } else {
+            let threads_block = Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title(format!("Threads ({})", threads.len()));
+
+            if threads.is_empty() {
+                let empty_text = if self.filter.is_empty() {
+                    "No threads yet. Press Ctrl-n to start one."
+                } else {
+                    "No threads match the filter"
+                };
+
+                frame.render_widget(
+                    Paragraph::new(empty_text).dark_gray().block(threads_block),
+                    body[0],
+                );
+            } else {
+                let mut table_state = TableState::default().with_selected(Some(self.selected));
+                frame.render_stateful_widget(
+                    self.thread_table(&threads).block(threads_block),
+                    body[0],
+                    &mut table_state,
+                );
+            }
+        }
+
+        let preview = Paragraph::new(self.preview(threads.get(self.selected).copied()))
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .title("Preview")
+                    .title(
+                        Title::from(
+                            self.error_text
+                                .as_deref()
+                                .map(|e| Span::from(e).red())
+                                .unwrap_or_default(),
+                        )
+                        .position(Position::Bottom)
+                        .alignment(Alignment::Left),
+                    ),
+            );
+
+        frame.render_widget(preview, body[1]);
+
+        let help = match &self.mode {
+            PickerMode::Browse => {
+                "Enter: open  Ctrl-n: new  Ctrl-r: rename  Ctrl-d: delete  Esc: clear filter/quit"
+            }
+            PickerMode::ConfirmDelete(_) => "Delete this thread permanently? (y/N)",
+            PickerMode::Rename { .. } => "Enter: save title  Esc: cancel",
+            PickerMode::ChoosePrompt { .. } => "Enter: start thread  Esc: back",
+        };
+
+        let help_style = match self.mode {
+            PickerMode::ConfirmDelete(_) => Style::default().fg(Color::Yellow).bold(),
+            _ => Style::default().dark_gray(),
+        };
+
+        frame.render_widget(Paragraph::new(help).style(help_style), chunks[2]);
+    }
+
+    fn event_loop(&mut self, terminal: &mut CrosstermTerminal) -> crate::Result<()> {
+        while !self.should_quit && self.choice.is_none() {
+            terminal.draw(|frame| self.ui(frame))?;
+
+            if let Event::Key(
+                key_event @ KeyEvent {
+                    kind: event::KeyEventKind::Press,
+                    ..
+                },
+            ) = event::read()?
+            {
+                // failing to rename or delete shouldn't close the picker
+                match self.update(key_event) {
+                    Ok(()) => self.error_text = None,
+                    Err(e) => self.error_text = Some(e.to_string()),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Show the picker until a thread is chosen, or a new one started.
+    /// Returns None if the user quit instead.
+    pub fn run(mut self) -> crate::Result<Option<Uuid>> {
+        App::startup()?;
+
+        let result = CrosstermTerminal::new(CrosstermBackend::new(std::io::stderr()))
+            .map_err(crate::Error::from)
+            .and_then(|mut terminal| self.event_loop(&mut terminal));
+
+        App::shutdown()?;
+        result?;
+
+        Ok(self.choice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert!(fuzzy_score("rst", "Rust lifetimes").is_some());
+        assert!(fuzzy_score("RUST", "rust lifetimes").is_some());
+        assert!(fuzzy_score("tsur", "rust").is_none());
+
+        // consecutive matches and word starts beat scattered ones
+        let exact = fuzzy_score("life", "Rust lifetimes").unwrap();
+        let scattered = fuzzy_score("life", "long interval of e").unwrap();
+        assert!(exact > scattered);
+
+        let word_start = fuzzy_score("wc", "word count").unwrap();
+        let mid_word = fuzzy_score("wc", "wordcount").unwrap();
+        assert!(word_start > mid_word);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::llm::ProviderKind;
+use crate::session::Message;
+
+use reqwest::header::HeaderMap;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+mod anthropic;
+mod ollama;
+mod openai;
+
+pub use anthropic::Anthropic;
+pub use ollama::Ollama;
+pub use openai::OpenAi;
+
+/// The result of parsing a chunk of a streamed response
+#[derive(Debug, Default)]
+pub struct StreamChunk {
+    /// Tokens of message content, in the order they were received
+    pub tokens: Vec<String>,
+
+    /// Incomplete trailing data, to be prepended to the next chunk
+    pub remainder: Option<String>,
+
+    /// Whether the provider has signalled the end of the response
+    pub done: bool,
+}
+
+/// A chat API backend. Each implementation handles its own wire format,
+/// so the rest of the client doesn't need to know which vendor it's talking to.
+pub trait Provider: Send + Sync {
+    /// Get the chat endpoint under the given base URL
+    fn chat_url(&self, base_url: &str) -> String;
+
+    /// Default headers (authorization, content type, etc.) to send with each request
+    fn headers(&self, api_key: Option<&str>) -> crate::Result<HeaderMap>;
+
+    /// Format the messages of a thread as a request body
+    fn request_body(&self, model: &str, messages: &[&Message], stream: bool) -> Value;
+
+    /// Parse as much of a streamed response as is currently available
+    fn parse_stream(&self, input: &str) -> crate::Result<StreamChunk>;
+
+    /// Extract the message content from a non-streamed response
+    fn parse_response(&self, response: &Value) -> Option<String>;
+
+    /// Extract a human-readable message from the body of an error response
+    fn error_message(&self, body: &Value) -> Option<String> {
+        // OpenAI and Anthropic nest an object with a message, Ollama just sends a string
+        body.pointer("/error/message")
+            .or_else(|| body.get("error"))
+            .and_then(|e| e.as_str())
+            .map(|s| s.into())
+    }
+}
+
+impl ProviderKind {
+    pub fn provider(&self) -> Box<dyn Provider> {
+        match self {
+            Self::OpenAi => Box::new(OpenAi),
+            Self::Anthropic => Box::new(Anthropic),
+            Self::Ollama => Box::new(Ollama),
+        }
+    }
+}
+
+/// Join a base URL and an endpoint path
+fn join_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path)
+}
+
+/// Line filter for formats where every line is a complete JSON value
+fn whole_line(line: &str) -> Option<&str> {
+    Some(line)
+}
+
+/// Parse line-delimited JSON values out of streamed text.
+/// `strip_line` maps each line to the JSON it contains, or `None` if the line should be skipped.
+/// Returns the parsed values, and any trailing text that could not be parsed yet.
+fn parse_json_lines<T, F>(input: &str, strip_line: F) -> crate::Result<(Vec<T>, Option<String>)>
+where
+    T: DeserializeOwned,
+    F: Fn(&str) -> Option<&str>,
+{
+    // anything after the final newline may still be cut off partway through,
+    // so hold it back until the rest of the line arrives
+    let (complete, trailing) = match input.rfind('\n') {
+        Some(i) => input.split_at(i + 1),
+        None => ("", input),
+    };
+
+    let input_lines: Vec<&str> = complete
+        .lines()
+        .map(|ln| ln.trim())
+        .filter_map(strip_line)
+        .map(|ln| ln.trim())
+        .filter(|ln| !ln.is_empty())
+        .collect();
+
+    let mut values = Vec::new();
+    let mut remainder_parts: Vec<&str> = Vec::new();
+
+    for (i, line) in input_lines.iter().enumerate() {
+        match serde_json::from_str::<T>(line) {
+            Ok(value) => values.push(value),
+            Err(e) if e.is_eof() => {
+                remainder_parts.extend(&input_lines[i..]);
+                break;
+            }
+            Err(e) => return Err(anyhow::anyhow!(e).into()),
+        }
+    }
+
+    if !trailing.trim().is_empty() {
+        remainder_parts.push(trailing);
+    }
+
+    let remainder = if remainder_parts.is_empty() {
+        None
+    } else {
+        Some(remainder_parts.join("\n"))
+    };
+
+    Ok((values, remainder))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_lines_holds_partial_line() {
+        let input = "{\"a\": 1}\n{\"a\": 2}\n{\"a\"";
+
+        let (values, remainder) = parse_json_lines::<Value, _>(input, whole_line).unwrap();
+
+        assert_eq!(values.len(), 2);
+        assert_eq!(remainder.as_deref(), Some("{\"a\""));
+
+        let (values, remainder) =
+            parse_json_lines::<Value, _>(&format!("{}: 3}}\n", remainder.unwrap()), whole_line)
+                .unwrap();
+
+        assert_eq!(values[0]["a"], 3);
+        assert!(remainder.is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use super::{join_url, parse_json_lines, Provider, StreamChunk};
+use crate::session::Message;
+
+use itertools::Itertools;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The Anthropic Messages API
+pub struct Anthropic;
+
+const API_VERSION: &str = "2023-06-01";
+
+/// The Messages API requires an explicit limit on the length of the reply
+const MAX_TOKENS: usize = 4096;
+
+#[derive(Deserialize, Debug)]
+struct TextDelta {
+    text: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+/// Server-sent events from the streaming Messages API
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    ContentBlockDelta {
+        delta: TextDelta,
+    },
+    MessageStop,
+    Error {
+        error: ApiError,
+    },
+
+    // message_start, content_block_start, ping, etc.
+    #[serde(other)]
+    Other,
+}
+
+/// Only `data:` lines carry a payload, the `event:` lines just repeat its type
+fn data_line(line: &str) -> Option<&str> {
+    line.strip_prefix("data:")
+}
+
+impl Provider for Anthropic {
+    fn chat_url(&self, base_url: &str) -> String {
+        join_url(base_url, "messages")
+    }
+
+    fn headers(&self, api_key: Option<&str>) -> crate::Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.insert("anthropic-version", HeaderValue::from_static(API_VERSION));
+
+        if let Some(key) = api_key {
+            headers.insert(
+                "x-api-key",
+                HeaderValue::from_str(key).map_err(|e| crate::Error::Other(e.into()))?,
+            );
+        }
+
+        Ok(headers)
+    }
+
+    fn request_body(&self, model: &str, messages: &[&Message], stream: bool) -> Value {
+        // the system prompt is a top-level field rather than a message
+        let system = messages
+            .iter()
+            .filter(|m| m.is_system())
+            .map(|m| m.content.as_str())
+            .join("\n\n");
+
+        json!({
+            "model": model,
+            "system": system,
+            "max_tokens": MAX_TOKENS,
+            "messages": messages
+                .iter()
+                .filter(|m| !m.is_system())
+                .map(|m| serde_json::to_value(m).unwrap())
+                .collect::<Vec<Value>>(),
+            "stream": stream,
+        })
+    }
+
+    fn parse_stream(&self, input: &str) -> crate::Result<StreamChunk> {
+        let (events, remainder) = parse_json_lines::<StreamEvent, _>(input, data_line)?;
+
+        let mut chunk = StreamChunk {
+            remainder,
+            ..Default::default()
+        };
+
+        for event in events {
+            match event {
+                StreamEvent::ContentBlockDelta { delta } => chunk.tokens.extend(delta.text),
+                StreamEvent::MessageStop => chunk.done = true,
+                StreamEvent::Error { error } => {
+                    return Err(
+                        anyhow::format_err!("Anthropic {}: {}", error.kind, error.message).into(),
+                    )
+                }
+                StreamEvent::Other => (),
+            }
+        }
+
+        Ok(chunk)
+    }
+
+    fn parse_response(&self, response: &Value) -> Option<String> {
+        response
+            .pointer("/content/0/text")
+            .and_then(|s| s.as_str())
+            .map(|s| s.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_events() {
+        let data = r#"event: message_start
+data: {"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","content":[]}}
+
+event: content_block_start
+data: {"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}
+
+event: ping
+data: {"type": "ping"}
+
+event: content_block_delta
+data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}
+
+event: content_block_delta
+data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"!"}}
+
+event: message_stop
+data: {"type":"message_stop"}
+
+"#;
+
+        let chunk = Anthropic.parse_stream(data).unwrap();
+
+        assert_eq!(chunk.tokens, ["Hello", "!"]);
+        assert!(chunk.done);
+        assert!(chunk.remainder.is_none());
+    }
+
+    #[test]
+    fn test_system_prompt_is_top_level() {
+        let system = Message::new(crate::session::Role::System, "Be brief", chrono::Utc::now());
+        let user = Message::new_user("Hi");
+
+        let body = Anthropic.request_body("claude-3-haiku", &[&system, &user], true);
+
+        assert_eq!(body["system"], "Be brief");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["messages"][0]["role"], "user");
+    }
+}
@@ -0,0 +1,107 @@
+use super::{join_url, parse_json_lines, whole_line, Provider, StreamChunk};
+use crate::session::Message;
+
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Ollama's native chat API, which streams newline-delimited JSON
+pub struct Ollama;
+
+#[derive(Deserialize, Debug)]
+struct ChatMessage {
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatChunk {
+    message: Option<ChatMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+impl Provider for Ollama {
+    fn chat_url(&self, base_url: &str) -> String {
+        join_url(base_url, "api/chat")
+    }
+
+    fn headers(&self, api_key: Option<&str>) -> crate::Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+
+        // not needed locally, but useful if ollama sits behind an authenticating proxy
+        if let Some(key) = api_key {
+            headers.insert(
+                header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", key))
+                    .map_err(|e| crate::Error::Other(e.into()))?,
+            );
+        }
+
+        Ok(headers)
+    }
+
+    fn request_body(&self, model: &str, messages: &[&Message], stream: bool) -> Value {
+        json!({
+            "model": model,
+            "messages": messages
+                .iter()
+                .map(|m| serde_json::to_value(m).unwrap())
+                .collect::<Vec<Value>>(),
+            "stream": stream,
+        })
+    }
+
+    fn parse_stream(&self, input: &str) -> crate::Result<StreamChunk> {
+        let (chunks, remainder) = parse_json_lines::<ChatChunk, _>(input, whole_line)?;
+
+        let mut parsed = StreamChunk {
+            remainder,
+            ..Default::default()
+        };
+
+        for chunk in chunks {
+            if let Some(error) = chunk.error {
+                return Err(anyhow::format_err!("Ollama: {}", error).into());
+            }
+
+            parsed.tokens.extend(chunk.message.map(|m| m.content));
+            parsed.done |= chunk.done;
+        }
+
+        Ok(parsed)
+    }
+
+    fn parse_response(&self, response: &Value) -> Option<String> {
+        response
+            .pointer("/message/content")
+            .and_then(|s| s.as_str())
+            .map(|s| s.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_ndjson() {
+        let data = concat!(
+            r#"{"model":"llama3","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":"Hi"},"done":false}"#,
+            "\n",
+            r#"{"model":"llama3","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":" there"},"done":false}"#,
+            "\n",
+            r#"{"model":"llama3","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":""},"done":true}"#,
+            "\n",
+        );
+
+        let chunk = Ollama.parse_stream(data).unwrap();
+
+        assert_eq!(chunk.tokens.concat(), "Hi there");
+        assert!(chunk.done);
+    }
+}
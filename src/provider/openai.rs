@@ -0,0 +1,159 @@
+use super::{join_url, parse_json_lines, Provider, StreamChunk};
+use crate::session::Message;
+
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The OpenAI chat completions API, and any server compatible with it
+pub struct OpenAi;
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CompletionDelta {
+    content: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CompletionChoice {
+    delta: CompletionDelta,
+    finish_reason: Option<String>,
+    index: usize,
+}
+
+///Struct representing a chunk from the streaming completions API
+#[derive(Serialize, Deserialize, Debug)]
+struct CompletionChunk {
+    id: String,
+    created: usize,
+    choices: Vec<CompletionChoice>,
+}
+
+impl CompletionChunk {
+    pub fn token(&self) -> Option<String> {
+        self.choices
+            .first()
+            .and_then(|c| c.delta.content.to_owned())
+    }
+}
+
+const DONE_MARKER: &str = "[DONE]";
+
+fn strip_data_prefix(line: &str) -> Option<&str> {
+    match line.trim_start_matches("data:").trim() {
+        DONE_MARKER => None,
+        ln => Some(ln),
+    }
+}
+
+fn try_parse_chunks(input: &str) -> crate::Result<(Option<Vec<CompletionChunk>>, Option<String>)> {
+    let (valid_chunks, remainder) = parse_json_lines(input, strip_data_prefix)?;
+
+    let return_chunks = if valid_chunks.is_empty() {
+        None
+    } else {
+        Some(valid_chunks)
+    };
+
+    Ok((return_chunks, remainder))
+}
+
+impl Provider for OpenAi {
+    fn chat_url(&self, base_url: &str) -> String {
+        join_url(base_url, "chat/completions")
+    }
+
+    fn headers(&self, api_key: Option<&str>) -> crate::Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+
+        if let Some(key) = api_key {
+            headers.insert(
+                header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", key))
+                    .map_err(|e| crate::Error::Other(e.into()))?,
+            );
+        }
+
+        Ok(headers)
+    }
+
+    fn request_body(&self, model: &str, messages: &[&Message], stream: bool) -> Value {
+        json!({
+            "model" : model,
+            "messages" : messages
+                .iter()
+                .map(|m| serde_json::to_value(m).unwrap())
+                .collect::<Vec<Value>>(),
+            "stream" : stream,
+        })
+    }
+
+    fn parse_stream(&self, input: &str) -> crate::Result<StreamChunk> {
+        let (chunks, remainder) = try_parse_chunks(input)?;
+
+        let done = input
+            .lines()
+            .any(|ln| ln.trim_start_matches("data:").trim() == DONE_MARKER);
+
+        Ok(StreamChunk {
+            tokens: chunks
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|c| c.token())
+                .collect(),
+            remainder,
+            done,
+        })
+    }
+
+    fn parse_response(&self, response: &Value) -> Option<String> {
+        response
+            .pointer("/choices/0/message/content")
+            .and_then(|s| s.as_str())
+            .map(|s| s.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_chunks() {
+        let data = r#"
+         data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1694268190,"model":"gpt-3.5-turbo-0613", "system_fingerprint": "fp_44709d6fcb", "choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}
+data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1694268190,"model":"gpt-3.5-turbo-0613", "system_fingerprint": "fp_44709d6fcb", "choices":[{"index":0,"delta":{"content":"!"},"finish_reason":null}]}
+data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1694268190,"model":"gpt-3.5-turbo-0613", "system_fingerprint": "fp_44709d6fcb", "choices":[{"index":0,"delta":{"content":" today"},"finish_reason":null}]}
+{"id":"chatcmpl-123","object":"chat.completion.chunk", "c
+        "#;
+
+        let (parsed, remaining) = try_parse_chunks(data).unwrap();
+
+        let parsed = parsed.unwrap();
+        let remaining = remaining.unwrap();
+
+        assert_eq!(
+            remaining.as_str(),
+            r#"{"id":"chatcmpl-123","object":"chat.completion.chunk", "c"#
+        );
+
+        for (token, expected) in parsed
+            .into_iter()
+            .map(|chunk| chunk.token())
+            .zip(["", "!", " today"])
+        {
+            assert!(token.is_some());
+            assert_eq!(token.unwrap().as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_done() {
+        let chunk = OpenAi.parse_stream("data: [DONE]\n\n").unwrap();
+        assert!(chunk.done);
+        assert!(chunk.tokens.is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::db::parse_uuid;
+use crate::message::timestamp_from_epoch;
+use crate::session::Role;
+
+use chrono::{DateTime, Utc};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Span;
+use rusqlite::{params, Connection};
+use uuid::Uuid;
+
+/// Marks the start of a matching term in a snippet
+pub const MATCH_START: char = '\u{2}';
+
+/// Marks the end of a matching term in a snippet
+pub const MATCH_END: char = '\u{3}';
+
+/// Number of words of context in each snippet
+const SNIPPET_WORDS: usize = 12;
+
+/// A thread title or message that matched a search
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub thread_id: Uuid,
+
+    /// The message that matched, or None if it was the thread title
+    pub message_id: Option<Uuid>,
+
+    /// Role of the message that matched, None for titles
+    pub role: Option<Role>,
+
+    /// Part of the matching text, with matches between `MATCH_START` and `MATCH_END`
+    pub snippet: String,
+
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl SearchResult {
+    /// The snippet on a single line, split into plain and matching parts
+    pub fn snippet_parts(&self) -> Vec<(&str, bool)> {
+        let mut parts = Vec::new();
+        let mut rest = self.snippet.as_str();
+
+        while let Some(start) = rest.find(MATCH_START) {
+            let (before, after) = rest.split_at(start);
+            let after = &after[MATCH_START.len_utf8()..];
+
+            let end = after.find(MATCH_END).unwrap_or(after.len());
+            parts.push((before, false));
+            parts.push((&after[..end], true));
+
+            rest = after.get(end + MATCH_END.len_utf8()..).unwrap_or("");
+        }
+
+        parts.push((rest, false));
+        parts.retain(|(text, _)| !text.is_empty());
+        parts
+    }
+
+    /// The snippet as text for a terminal, with matches in bold
+    pub fn ansi_snippet(&self) -> String {
+        self.snippet_parts()
+            .into_iter()
+            .map(|(text, is_match)| {
+                if is_match {
+                    format!("\x1b[1;33m{}\x1b[0m", text)
+                } else {
+                    text.to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// The snippet as spans for the TUI, with matches highlighted
+    pub fn snippet_spans(&self) -> Vec<Span<'_>> {
+        self.snippet_parts()
+            .into_iter()
+            .map(|(text, is_match)| {
+                if is_match {
+                    Span::styled(
+                        text,
+                        Style::default()
+                            .fg(ratatui::style::Color::Yellow)
+                            .add_modifier(Modifier::BOLD),
+                    )
+                } else {
+                    Span::raw(text)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Turn what the user typed into an FTS5 query, matching every word as a prefix.
+/// Each word is quoted, so punctuation can't be mistaken for query syntax.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Find thread titles and messages containing all the words of the query, titles first and
+/// then messages, each best match first. System prompts are not searched.
+/// Only results from threads for which `include` is true count towards the limit.
+pub fn search(
+    conn: &Connection,
+    query: &str,
+    limit: usize,
+    include: impl Fn(Uuid) -> bool,
+) -> crate::Result<Vec<SearchResult>> {
+    let Some(fts_query) = fts_query(query) else {
+        return Ok(Vec::new());
+    };
+
+    let snippet_fn = |table: &str| {
+        format!(
+            "replace(snippet({}, 0, char({}), char({}), '...', {}), char(10), ' ')",
+            table, MATCH_START as u32, MATCH_END as u32, SNIPPET_WORDS
+        )
+    };
+
+    // ranks from the two tables can't be compared, so titles, being shorter, come first
+    let mut results: Vec<SearchResult> = conn
+        .prepare(&format!(
+            r#"
+            SELECT title.id, {}
+            FROM title_fts JOIN title ON title.rowid = title_fts.rowid
+            WHERE title_fts MATCH ?1
+            ORDER BY rank
+            "#,
+            snippet_fn("title_fts")
+        ))?
+        .query_map(params![&fts_query], |row| {
+            Ok(SearchResult {
+                thread_id: parse_uuid(0, &row.get::<_, String>(0)?)?,
+                message_id: None,
+                role: None,
+                snippet: row.get(1)?,
+                timestamp: None,
+            })
+        })?
+        .filter(|result| result.as_ref().map_or(true, |r| include(r.thread_id)))
+        .take(limit)
+        .collect::<rusqlite::Result<_>>()?;
+
+    let message_results = conn
+        .prepare(&format!(
+            r#"
+            SELECT message.thread_id, message.id, message.role, message.timestamp, {}
+            FROM message_fts JOIN message ON message.rowid = message_fts.rowid
+            WHERE message_fts MATCH ?1 AND message.role != ?2
+            ORDER BY rank
+            "#,
+            snippet_fn("message_fts")
+        ))?
+        .query_and_then(
+            params![&fts_query, Role::System.to_num()],
+            |row| -> crate::Result<SearchResult> {
+                Ok(SearchResult {
+                    thread_id: parse_uuid(0, &row.get::<_, String>(0)?)?,
+                    message_id: Some(parse_uuid(1, &row.get::<_, String>(1)?)?),
+                    role: Some(Role::from_num(row.get::<_, i64>(2)? as usize)?),
+                    snippet: row.get(4)?,
+                    timestamp: Some(timestamp_from_epoch(row.get(3)?)),
+                })
+            },
+        )?
+        .filter(|result| result.as_ref().map_or(true, |r| include(r.thread_id)))
+        .take(limit - results.len())
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    results.extend(message_results);
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{setup_table_schema, DbStore};
+    use crate::llm::LlmModel;
+    use crate::session::{Message, Thread};
+
+    #[test]
+    fn test_fts_query_quotes_terms() {
+        assert_eq!(
+            fts_query("rust \"lifetimes\" foo-bar").as_deref(),
+            Some("\"rust\"* \"\"\"lifetimes\"\"\"* \"foo-bar\"*")
+        );
+        assert_eq!(fts_query("   "), None);
+    }
+
+    #[test]
+    fn test_search_messages_and_titles() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let mut thread = Thread::new(
+            vec![Message::new(
+                Role::System,
+                "You know about borrowing",
+                Utc::now(),
+            )],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+        thread.add_message(Message::new_user("How does the borrow checker work?"));
+        thread.add_message(Message::new_asst("It tracks lifetimes.\nMostly."));
+        thread.set_title("Borrowing in Rust");
+        thread.to_db(&mut conn).unwrap();
+
+        let results = search(&conn, "borrow", 10, |_| true).unwrap();
+        assert_eq!(results.len(), 2);
+
+        // titles come before messages, and the system prompt isn't searched
+        assert!(results[0].message_id.is_none());
+        assert_eq!(results[1].role, Some(Role::User));
+        assert_eq!(results[1].message_id, Some(thread.non_sys_messages()[0].id));
+        assert_eq!(
+            results[1].snippet_parts(),
+            vec![
+                ("How does the ", false),
+                ("borrow", true),
+                (" checker work?", false)
+            ]
+        );
+
+        let results = search(&conn, "lifetime mostly", 10, |_| true).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].snippet.contains('\n'));
+
+        // removed messages are no longer found
+        thread.drop_from_db(&mut conn).unwrap();
+        assert!(search(&conn, "borrow", 10, |_| true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_titles_first_and_limit_after_filtering() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup_table_schema(&conn).unwrap();
+
+        let mut threads = Vec::new();
+
+        for title in ["Tokio runtimes", "Async with tokio"] {
+            let mut thread = Thread::new(
+                vec![Message::new(Role::System, "prompt", Utc::now())],
+                LlmModel::default(),
+                Uuid::new_v4(),
+            );
+            thread.add_message(Message::new_user("Tokio tokio tokio"));
+            thread.add_message(Message::new_asst("Something else entirely"));
+            thread.set_title(title);
+            thread.to_db(&mut conn).unwrap();
+            threads.push(thread);
+        }
+
+        // a title is found before a message that mentions the words more often
+        let results = search(&conn, "tokio", 10, |_| true).unwrap();
+        assert_eq!(results.len(), 4);
+        assert!(results[..2].iter().all(|r| r.message_id.is_none()));
+        assert!(results[2..].iter().all(|r| r.message_id.is_some()));
+
+        // results that are left out don't take up any of the limit
+        let shown = threads[1].id;
+        let results = search(&conn, "tokio", 2, |id| id == shown).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.thread_id == shown));
+        assert!(results[1].message_id.is_some());
+    }
+}
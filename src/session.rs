@@ -1,8 +1,11 @@
 use crate::client::fetch_thread_name;
 use crate::config::PromptSetting;
-use crate::db::{init_db,  DbStore};
+use crate::db::{init_db, DbStore};
+use crate::import::same_conversation;
 use crate::llm::LlmModel;
+use crate::message::local_time_fmt;
 pub use crate::message::{CodeBlock, Message, Role};
+use crate::search::{search, SearchResult};
 
 // use anyhow::format_err;
 use chrono::{DateTime, Utc};
@@ -14,13 +17,18 @@ use ratatui::style::Style;
 use ratatui::text::Span;
 use ratatui::text::{Line, Text};
 
-use serde_json::{self, json, Value};
 use std::borrow::Cow;
 use std::collections::HashMap;
 
 use std::str::FromStr;
 use uuid::Uuid;
 
+/// How many characters of a thread's id are shown in lists
+pub const SHORT_ID_LEN: usize = 8;
+
+/// The fewest characters of an id that are looked up as one, so short words aren't mistaken for ids
+const MIN_ID_PREFIX_LEN: usize = 4;
+
 // get an initial slice of a string, ending with elipsis,
 //desired_length is the maximum final length including elipsis.
 pub fn string_preview(text: &str, desired_length: usize) -> Cow<'_, str> {
@@ -36,9 +44,34 @@ pub fn string_preview(text: &str, desired_length: usize) -> Cow<'_, str> {
             .join(""),
     )
 }
+/// The id of the only thread in `matches`, or an error listing them if there isn't just one
+fn one_match(spec: &str, matches: &[&Thread]) -> crate::Result<Uuid> {
+    match matches {
+        [thread] => Ok(thread.id),
+        [] => Err(anyhow::format_err!("No thread matched '{}'", spec).into()),
+        _ => {
+            let candidates = matches
+                .iter()
+                .map(|t| format!("\t{} {}", t.short_id(), t.display_title()))
+                .join("\n");
+
+            Err(anyhow::format_err!("'{}' could refer to:\n{}", spec, candidates).into())
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Thread {
+    /// Every message in the thread, across all branches, in the order they were added
     messages: Vec<Message>,
+
+    /// The latest message of the branch currently being shown
+    head: Option<Uuid>,
+
+    /// Positions in `messages` of the branch ending at `head`, first message first.
+    /// Kept up to date by `set_head`, so the branch isn't worked out again each time it's needed.
+    branch: Vec<usize>,
+
     pub model: LlmModel,
 
     pub id: Uuid,
@@ -48,16 +81,58 @@ pub struct Thread {
     incoming: Option<Message>,
 
     thread_title: Option<String>,
+
+    /// The thread this one was forked from, if any
+    forked_from: Option<Uuid>,
 }
 
 impl Thread {
+    /// Create a thread from a single, unbranched list of messages
     pub fn new(messages: Vec<Message>, model: LlmModel, id: Uuid) -> Self {
-        Self {
-            messages,
+        let mut thread = Self {
             model,
             id,
             ..Default::default()
+        };
+
+        for msg in messages {
+            thread.add_message(msg);
         }
+
+        thread
+    }
+
+    /// Create an empty thread, beginning with the given prompt
+    pub fn with_prompt(prompt: &PromptSetting) -> Self {
+        let messages = vec![Message::new(Role::System, &prompt.prompt, Utc::now())];
+
+        let mut thread = Self::new(messages, prompt.model.clone(), Uuid::new_v4());
+        thread.prompt = prompt.clone();
+
+        thread
+    }
+
+    /// Create a thread from messages that already have their parents set.
+    /// If `head` is missing or unknown, the most recent message is used.
+    pub fn from_tree(
+        messages: Vec<Message>,
+        head: Option<Uuid>,
+        model: LlmModel,
+        id: Uuid,
+    ) -> Self {
+        let head = head
+            .filter(|h| messages.iter().any(|m| m.id == *h))
+            .or_else(|| messages.iter().max_by_key(|m| m.timestamp).map(|m| m.id));
+
+        let mut thread = Self {
+            messages,
+            model,
+            id,
+            ..Default::default()
+        };
+        thread.set_head(head);
+
+        thread
     }
 
     pub fn thread_title(&self) -> Option<&str> {
@@ -77,11 +152,16 @@ impl Thread {
         self.thread_title = Some(title.into())
     }
 
+    pub fn forked_from(&self) -> Option<Uuid> {
+        self.forked_from
+    }
+
+    pub fn set_forked_from(&mut self, origin: Uuid) {
+        self.forked_from = Some(origin)
+    }
+
     pub fn list_preview(&self) -> Option<String> {
-        let local_time_fmt = self
-            .init_time()?
-            .with_timezone(&chrono::Local)
-            .format("%Y-%m-%d %H:%M");
+        let init_time = local_time_fmt(self.init_time()?);
 
         let preview_msg = if let Some(title) = self.thread_title() {
             title.to_string()
@@ -90,9 +170,9 @@ impl Thread {
                 .map(|m| string_preview(&m.content, 200).to_string())?
         };
 
-        Some(format!("{} {}", local_time_fmt, preview_msg))
+        Some(format!("{} {}", init_time, preview_msg))
     }
-    pub fn message_display_header(&self, role: Role) -> Span {
+    pub fn message_display_header(&self, role: Role) -> Span<'_> {
         match role {
             Role::User => Span::styled(
                 "User",
@@ -122,22 +202,110 @@ impl Thread {
     }
 
     pub fn non_sys_messages(&self) -> Vec<&Message> {
-        self.messages
-            .iter()
+        self.messages()
+            .into_iter()
             .filter(|m| !m.is_system())
             .collect_vec()
     }
 
+    /// Get the messages of the current branch, from the start of the thread to its head
     pub fn messages(&self) -> Vec<&Message> {
+        self.branch.iter().map(|&i| &self.messages[i]).collect()
+    }
+
+    /// Show the branch ending at the given message.
+    /// This must be called whenever `head` or `messages` changes, to keep `branch` up to date.
+    fn set_head(&mut self, head: Option<Uuid>) {
+        let by_id: HashMap<Uuid, usize> = self
+            .messages
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (m.id, i))
+            .collect();
+
+        self.head = head;
+        self.branch.clear();
+
+        let mut next = head.and_then(|id| by_id.get(&id));
+
+        while let Some(&i) = next {
+            self.branch.push(i);
+            next = self.messages[i].parent_id.and_then(|id| by_id.get(&id));
+        }
+
+        self.branch.reverse();
+    }
+
+    /// Get every message in the thread, including those on branches not currently shown
+    pub fn all_messages(&self) -> Vec<&Message> {
         self.messages.iter().collect()
     }
+
+    /// Id of the last message in the current branch
+    pub fn head(&self) -> Option<Uuid> {
+        self.head
+    }
+
+    pub fn message_by_id(&self, id: Uuid) -> Option<&Message> {
+        self.messages.iter().find(|m| m.id == id)
+    }
+
+    /// Switch to the branch containing the given message, if it isn't already shown.
+    /// Returns false if there is no such message.
+    pub fn show_message(&mut self, id: Uuid) -> bool {
+        if self.message_by_id(id).is_none() {
+            return false;
+        }
+
+        if !self.messages().iter().any(|m| m.id == id) {
+            self.set_head(Some(self.latest_leaf(id)));
+        }
+
+        true
+    }
+
+    /// Get the replies to a message, oldest first
+    fn children(&self, id: Uuid) -> Vec<&Message> {
+        self.messages
+            .iter()
+            .filter(|m| m.parent_id == Some(id))
+            .sorted_by_key(|m| m.timestamp)
+            .collect()
+    }
+
+    /// Get all versions of a message at its place in the tree (including itself), oldest first
+    fn siblings(&self, msg: &Message) -> Vec<&Message> {
+        self.messages
+            .iter()
+            .filter(|m| m.parent_id == msg.parent_id)
+            .sorted_by_key(|m| m.timestamp)
+            .collect()
+    }
+
+    /// Follow the most recent replies down from a message to the end of its branch
+    fn latest_leaf(&self, id: Uuid) -> Uuid {
+        let mut leaf = id;
+
+        while let Some(child) = self.children(leaf).last() {
+            leaf = child.id;
+        }
+
+        leaf
+    }
     /// Get the prompt used to begin this thread
     pub fn prompt(&self) -> &PromptSetting {
         &self.prompt
     }
 
+    pub fn set_prompt(&mut self, prompt: PromptSetting) {
+        self.prompt = prompt
+    }
+
     pub fn code_blocks(&self) -> Vec<&CodeBlock> {
-        self.messages.iter().flat_map(|m| m.code_blocks()).collect()
+        self.messages()
+            .into_iter()
+            .flat_map(|m| m.code_blocks())
+            .collect()
     }
 
     pub fn set_incoming_message(&mut self, text: &str) {
@@ -158,7 +326,7 @@ impl Thread {
     /// Commit the completed message to the thread, and reset state for the next incoming message
     pub fn commit_message(&mut self) -> crate::Result<()> {
         if let Some(msg) = self.incoming.take() {
-            self.messages.push(msg);
+            self.add_message(msg);
 
             if self.thread_title().is_none() && self.non_sys_messages().len() >= 2 {
                 self.update_thread_name()?;
@@ -167,25 +335,85 @@ impl Thread {
 
         Ok(())
     }
+    /// Commit what has arrived of an interrupted reply, marked as truncated
+    pub fn commit_truncated_message(&mut self) -> crate::Result<()> {
+        if let Some(msg) = self.incoming.as_mut() {
+            msg.truncated = true;
+        }
+
+        self.commit_message()
+    }
+
     pub fn clear_incoming_message(&mut self) {
         self.incoming = None;
     }
 
-    /// Get all messages in this thread as they will be displayed
-    pub fn tui_formatted_messages(&self, line_width: u16) -> Vec<Text> {
+    /// Get the reply currently being received, if any part of it has arrived
+    pub fn incoming_message(&self) -> Option<&Message> {
+        self.incoming.as_ref()
+    }
+
+    /// Remove and return the most recent message if it is from the user,
+    /// i.e. it has not been replied to yet.
+    /// If it was an edit of an earlier message, the branch it was edited from is shown again.
+    pub fn take_unanswered_message(&mut self) -> Option<Message> {
+        let last = self.last_message().filter(|m| m.is_user())?.id;
+
+        if !self.children(last).is_empty() {
+            return None;
+        }
+
+        let index = self.messages.iter().position(|m| m.id == last)?;
+        let msg = self.messages.remove(index);
+
+        let latest_sibling = self.siblings(&msg).last().map(|m| m.id);
+        self.set_head(
+            latest_sibling
+                .map(|sibling| self.latest_leaf(sibling))
+                .or(msg.parent_id),
+        );
+
+        Some(msg)
+    }
+
+    /// Get all messages in this thread as they will be displayed.
+    /// The header of the `selected` message is highlighted.
+    pub fn tui_formatted_messages(&self, line_width: u16, selected: Option<Uuid>) -> Vec<Text<'_>> {
         let mut msgs_buf: Vec<Text> = Vec::new();
         let mut block_counter = 1usize;
         let mut all_blocks = Vec::new();
 
         for msg in self
-            .messages
-            .iter()
-            .map(Some)
-            .chain(std::iter::once(self.incoming.as_ref()))
-            .flatten()
+            .messages()
+            .into_iter()
+            .chain(self.incoming.as_ref())
             .filter(|m| !m.is_system())
         {
-            let header_line = Line::from(vec![self.message_display_header(msg.role)]);
+            let mut role_header = self.message_display_header(msg.role);
+
+            if selected == Some(msg.id) {
+                role_header.patch_style(Style::default().add_modifier(Modifier::REVERSED));
+            }
+
+            let mut header_spans = vec![role_header];
+
+            if let Some((position, total)) = self.branch_position(msg.id) {
+                header_spans.push(Span::styled(
+                    format!(" ({}/{})", position, total),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+
+            if msg.truncated {
+                header_spans.push(Span::styled(
+                    " [truncated]",
+                    Style::default()
+                        .fg(Color::DarkGray)
+                        .add_modifier(Modifier::ITALIC),
+                ));
+            }
+
+            let header_line = Line::from(header_spans);
 
             let text = msg.formatted_content(&mut block_counter, line_width);
 
@@ -193,7 +421,7 @@ impl Thread {
 
             let amended_lines = [header_line]
                 .into_iter()
-                .chain(text.lines.into_iter())
+                .chain(text.lines)
                 .chain(std::iter::once("\n".into()))
                 .collect_vec();
 
@@ -207,37 +435,154 @@ impl Thread {
         self.id.as_simple().to_string()
     }
 
-    /// Format this thread as JSON suitible for use with the HTTP API
-    pub fn as_json_body(&self) -> Value {
-        json!({
-            "model" : self.model.to_string(),
-            "messages" : self.messages
-                .iter()
-                .map(|m| serde_json::to_value(m).unwrap())
-                .collect::<Vec<Value>>(),
+    /// Whether the thread has no messages besides its prompt, so isn't listed
+    pub fn is_empty(&self) -> bool {
+        self.non_sys_messages().is_empty()
+    }
 
-            "stream" : true,
-           // "max_tokens": MAX_TOKENS
-        })
+    /// The start of this thread's id, which is enough to pick it out in practice
+    pub fn short_id(&self) -> String {
+        self.str_id()[..SHORT_ID_LEN].to_string()
     }
 
     ///Return the time the first non-system message was sent
     pub fn init_time(&self) -> Option<DateTime<Utc>> {
-        self.messages.first().map(|m| m.timestamp)
+        self.messages().first().map(|m| m.timestamp)
+    }
+
+    /// When the most recent message was added, on any branch
+    pub fn last_update(&self) -> Option<DateTime<Utc>> {
+        self.messages.iter().map(|m| m.timestamp).max()
     }
 
-    pub fn add_message(&mut self, msg: Message) {
+    /// Add a message to the end of the current branch
+    pub fn add_message(&mut self, mut msg: Message) {
+        msg.parent_id = self.head;
+        let id = msg.id;
         self.messages.push(msg);
+        self.set_head(Some(id));
+    }
+
+    /// Start a new branch from an earlier user message, replacing it with an edited version.
+    /// The original message and everything after it are kept on their own branch.
+    pub fn edit_message(&mut self, id: Uuid, content: &str) -> crate::Result<()> {
+        let original = self
+            .message_by_id(id)
+            .ok_or_else(|| anyhow::format_err!("No message with id {id}"))?;
+
+        if !original.is_user() {
+            return Err(anyhow::format_err!("Only your own messages can be edited").into());
+        }
+
+        let mut edited = Message::new_user(content);
+        edited.parent_id = original.parent_id;
+
+        let id = edited.id;
+        self.messages.push(edited);
+        self.set_head(Some(id));
+
+        Ok(())
+    }
+
+    /// Get the other replies that were generated in place of the last assistant message
+    pub fn alternatives(&self) -> Vec<&Message> {
+        match self.last_message() {
+            Some(last) => self
+                .siblings(last)
+                .into_iter()
+                .filter(|m| m.id != last.id)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Move back from the last assistant message, so a new reply can be requested in its place.
+    /// The old reply is kept as an alternative.
+    pub fn regenerate_reply(&mut self) -> crate::Result<()> {
+        match self.last_message() {
+            Some(msg) if msg.is_assistant() => {
+                self.set_head(msg.parent_id);
+                Ok(())
+            }
+            Some(_) => Err(anyhow::format_err!("The most recent message is not a reply").into()),
+            None => Err(anyhow::format_err!("The thread has no messages").into()),
+        }
+    }
+
+    /// Undo `regenerate_reply`, putting the most recent alternative back in place.
+    /// Returns false if there was nothing to restore.
+    pub fn restore_alternative(&mut self) -> bool {
+        let Some(last) = self.last_message().filter(|m| m.is_user()).map(|m| m.id) else {
+            return false;
+        };
+
+        let leaf = self.latest_leaf(last);
+        self.set_head(Some(leaf));
+
+        leaf != last
+    }
+
+    /// Position of a message among the other versions of it, counting from 1, and the total number of them.
+    /// None if there is only the one version.
+    pub fn branch_position(&self, id: Uuid) -> Option<(usize, usize)> {
+        let msg = self.message_by_id(id)?;
+        let siblings = self.siblings(msg);
+
+        if siblings.len() < 2 {
+            return None;
+        }
+
+        let position = siblings.iter().position(|m| m.id == id)?;
+
+        Some((position + 1, siblings.len()))
+    }
+
+    /// Position of the last reply among all its alternatives, counting from 1, and the total number of them.
+    /// None if there are no alternatives.
+    pub fn reply_position(&self) -> Option<(usize, usize)> {
+        let current = self.last_message().filter(|m| m.is_assistant())?;
+        self.branch_position(current.id)
+    }
+
+    /// Show the next (or previous) version of a message in the current branch, ordered by when they were created,
+    /// along with the most recent conversation that followed it.
+    /// Returns the id of the version now shown, or None if there is no other version.
+    pub fn switch_branch(&mut self, id: Uuid, forward: bool) -> Option<Uuid> {
+        let (position, total) = self.branch_position(id)?;
+        let msg = self.message_by_id(id)?;
+
+        let next_index = if forward {
+            position % total
+        } else {
+            (position + total - 2) % total
+        };
+
+        let next = self.siblings(msg)[next_index].id;
+        self.set_head(Some(self.latest_leaf(next)));
+
+        Some(next)
+    }
+
+    /// Swap the last reply for the next (or previous) alternative, ordered by when they were created
+    pub fn cycle_alternative(&mut self, forward: bool) -> bool {
+        match self
+            .last_message()
+            .filter(|m| m.is_assistant())
+            .map(|m| m.id)
+        {
+            Some(last) => self.switch_branch(last, forward).is_some(),
+            None => false,
+        }
     }
 
     /// Get the first non-system message in this thread
     pub fn first_message(&self) -> Option<&Message> {
-        self.messages.iter().find(|m| !m.is_system()).to_owned()
+        self.messages().into_iter().find(|m| !m.is_system())
     }
 
     /// Get the most recent message (could be a system message).
     pub fn last_message(&self) -> Option<&Message> {
-        self.messages.iter().last()
+        self.head.and_then(|id| self.message_by_id(id))
     }
 
     pub fn update_thread_name(&mut self) -> crate::Result<()> {
@@ -262,6 +607,18 @@ impl Session {
         })
     }
 
+    /// A session with an empty database that's only kept in memory
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::setup_table_schema(&db).unwrap();
+
+        Self {
+            threads: HashMap::new(),
+            db,
+        }
+    }
+
     pub fn load_threads(&mut self) -> crate::Result<()> {
         let loaded_threads = Thread::get_all(&mut self.db)?
             .into_iter()
@@ -285,12 +642,8 @@ impl Session {
     /// Create a new thread with the given prompt.
     /// Returns a unique ID that can be used to access the thread
     pub fn new_thread(&mut self, prompt: &PromptSetting) -> crate::Result<Uuid> {
-        let messages = vec![Message::new(Role::System, &prompt.prompt, Utc::now())];
-
-        let id = Uuid::new_v4();
-
-        let mut thread = Thread::new(messages, prompt.model, id);
-        thread.prompt = prompt.clone();
+        let thread = Thread::with_prompt(prompt);
+        let id = thread.id;
 
         if self.threads.insert(id, thread).is_some() {
             Err(anyhow::format_err!("Thread ID was already present: {id}").into())
@@ -299,6 +652,79 @@ impl Session {
         }
     }
 
+    /// Copy a thread into a new, independent one with the same prompt.
+    /// Only the branch currently shown is copied, up to and including `up_to_message` if given.
+    /// Returns the ID of the new thread.
+    pub fn fork_thread(&mut self, id: Uuid, up_to_message: Option<Uuid>) -> crate::Result<Uuid> {
+        let original = self
+            .thread_by_id(id)
+            .ok_or_else(|| anyhow::format_err!("No thread with ID {id}"))?;
+
+        let mut branch = original.messages();
+
+        if let Some(last_id) = up_to_message {
+            let last_index = branch
+                .iter()
+                .position(|m| m.id == last_id)
+                .ok_or_else(|| anyhow::format_err!("Message {last_id} is not in this thread"))?;
+
+            branch.truncate(last_index + 1);
+        }
+
+        // the copies need their own ids, so the two threads can be stored side by side
+        let messages = branch
+            .into_iter()
+            .map(|m| {
+                let mut copy = m.clone();
+                copy.id = Uuid::new_v4();
+                copy
+            })
+            .collect();
+
+        let fork_id = Uuid::new_v4();
+
+        let mut fork = Thread::new(messages, original.model.clone(), fork_id);
+        fork.prompt = original.prompt.clone();
+        fork.thread_title = original.thread_title.clone();
+        fork.forked_from = Some(id);
+
+        if self.threads.insert(fork_id, fork).is_some() {
+            Err(anyhow::format_err!("Thread ID was already present: {fork_id}").into())
+        } else {
+            Ok(fork_id)
+        }
+    }
+
+    /// Add an imported thread to the session, and save it.
+    /// Returns false if the same conversation was already here, in which case nothing is added.
+    pub fn import_thread(&mut self, thread: Thread) -> crate::Result<bool> {
+        let already_present = self.threads.contains_key(&thread.id)
+            || self
+                .threads
+                .values()
+                .any(|existing| same_conversation(existing, &thread));
+
+        if already_present {
+            return Ok(false);
+        }
+
+        thread.to_db(&mut self.db)?;
+        self.threads.insert(thread.id, thread);
+
+        Ok(true)
+    }
+
+    /// Give a thread a new title, and save it
+    pub fn rename_thread(&mut self, id: Uuid, title: &str) -> crate::Result<()> {
+        let thread = self
+            .threads
+            .get_mut(&id)
+            .ok_or_else(|| anyhow::format_err!("No thread with ID {id}"))?;
+
+        thread.set_title(title);
+        thread.to_db(&mut self.db)
+    }
+
     /// Get an (immutable) reference to a thread from its id
     pub fn thread_by_id(&self, id: Uuid) -> Option<&Thread> {
         self.threads.get(&id)
@@ -312,19 +738,76 @@ impl Session {
     /// Get references to the Ids and contents of all non-empty threads,
     /// sorted ascending by creation time.
     pub fn ordered_threads(&self) -> Vec<(&Uuid, &Thread)> {
+        // forks share their creation time with the original, so break ties by id to keep indices stable
         self.threads
             .iter()
-            .filter(|(_, t)| !t.non_sys_messages().is_empty())
-            .sorted_by_key(|(_, t)| t.init_time().expect("Thread has no messages"))
+            .filter(|(_, t)| !t.is_empty())
+            .sorted_by_key(|(id, t)| (t.init_time().expect("Thread has no messages"), **id))
             .collect_vec()
     }
 
-    /// Get the number of threads in this session with at least one message
-    pub fn nonempty_count(&self) -> usize {
-        self.threads
-            .iter()
-            .filter(|(_, t)| !t.messages.is_empty())
-            .count()
+    /// Find a thread from its position in `ordered_threads` (counting from 1),
+    /// its id or the start of it, or part of its title.
+    /// Anything as long as a short id is only taken as an id, and a number that's
+    /// also the start of another thread's id is an error rather than a guess.
+    pub fn find_thread(&self, spec: &str) -> crate::Result<Uuid> {
+        let ordered = self.ordered_threads();
+
+        let id_prefix = spec.replace('-', "").to_lowercase();
+        let is_id_prefix = id_prefix.len() >= MIN_ID_PREFIX_LEN
+            && id_prefix.chars().all(|c| c.is_ascii_hexdigit());
+
+        let mut matches = match is_id_prefix {
+            true => ordered
+                .iter()
+                .map(|(_, t)| *t)
+                .filter(|t| t.str_id().starts_with(&id_prefix))
+                .collect_vec(),
+            false => Vec::new(),
+        };
+
+        if is_id_prefix && id_prefix.len() >= SHORT_ID_LEN {
+            return one_match(spec, &matches);
+        }
+
+        if let Some((_, thread)) = spec
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| ordered.get(i))
+        {
+            matches.retain(|t| t.id != thread.id);
+            matches.insert(0, thread);
+
+            return one_match(spec, &matches);
+        }
+
+        let needle = spec.to_lowercase();
+
+        for (_, thread) in &ordered {
+            if thread.display_title().to_lowercase().contains(&needle)
+                && !matches.iter().any(|t| t.id == thread.id)
+            {
+                matches.push(thread);
+            }
+        }
+
+        one_match(spec, &matches)
+    }
+
+    /// The thread with the most recent message
+    pub fn last_thread(&self) -> Option<Uuid> {
+        self.ordered_threads()
+            .into_iter()
+            .max_by_key(|(_, t)| t.last_update())
+            .map(|(id, _)| *id)
+    }
+
+    /// Search the titles and messages of the threads in `ordered_threads`
+    pub fn search(&self, query: &str, limit: usize) -> crate::Result<Vec<SearchResult>> {
+        search(&self.db, query, limit, |id| {
+            self.thread_by_id(id).is_some_and(|t| !t.is_empty())
+        })
     }
 
     pub fn save_to_db(&mut self) -> crate::Result<()> {
@@ -336,60 +819,239 @@ impl Session {
     }
 }
 
-#[cfg(feature = "debug-dump")]
-impl Session {
-    pub fn dump_all(&self) {
-        for (_, thread) in self.ordered_threads() {
-            thread.dump()
-        }
-    }
-}
-
 impl Drop for Session {
     fn drop(&mut self) {
         self.save_to_db().unwrap();
     }
 }
 
-#[cfg(feature = "debug-dump")]
-impl Thread {
-    pub fn dump_to_file(&self, dest: impl AsRef<Path>) {
-        let json = self.as_json_body();
-        let dest_file = PathBuf::from(dest.as_ref());
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    fn reply_at(text: &str, secs: i64) -> Message {
+        Message::new(
+            Role::Assistant,
+            text,
+            DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_regenerate_and_cycle() {
+        let mut thread = Thread::default();
+        thread.add_message(Message::new_user("question"));
+        thread.add_message(reply_at("first", 1));
+
+        thread.regenerate_reply().unwrap();
+        assert!(thread.last_message().unwrap().is_user());
+
+        thread.add_message(reply_at("second", 2));
+        assert_eq!(thread.reply_position(), Some((2, 2)));
 
-        let json_content = serde_json::to_string_pretty(&json).expect("Failed to write to json");
-        std::fs::write(dest_file, json_content.as_bytes()).unwrap();
+        assert!(thread.cycle_alternative(true));
+        assert_eq!(thread.last_message().unwrap().content, "first");
+        assert_eq!(thread.reply_position(), Some((1, 2)));
+
+        assert!(thread.cycle_alternative(false));
+        assert_eq!(thread.last_message().unwrap().content, "second");
+
+        // a new question leaves the old alternatives behind
+        let mut later = Message::new_user("another question");
+        later.timestamp += Duration::seconds(10);
+        thread.add_message(later);
+        assert!(thread.alternatives().is_empty());
+        assert!(!thread.cycle_alternative(true));
     }
 
-    pub fn dump_location(&self) -> PathBuf {
-        #[cfg(debug_assertions)]
-        let p = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_assets");
+    #[test]
+    fn test_restore_alternative() {
+        let mut thread = Thread::default();
+        thread.add_message(Message::new_user("question"));
+        thread.add_message(reply_at("only", 1));
 
-        #[cfg(not(debug_assertions))]
-        let p = std::env::current_dir().expect("Could not get current working dir");
+        thread.regenerate_reply().unwrap();
+        assert!(thread.restore_alternative());
+        assert_eq!(thread.last_message().unwrap().content, "only");
+        assert!(thread.alternatives().is_empty());
+    }
 
-        if !p.exists() {
-            std::fs::create_dir_all(&p).expect("Could not create directory");
-        }
+    #[test]
+    fn test_edit_message_branches() {
+        let mut thread = Thread::default();
+        thread.add_message(Message::new_user("first question"));
+        thread.add_message(reply_at("first answer", 1));
+        thread.add_message(Message::new_user("second question"));
+        thread.add_message(reply_at("second answer", 2));
+
+        let original_id = thread.non_sys_messages()[0].id;
+        thread.edit_message(original_id, "edited question").unwrap();
+
+        let branch = thread.messages();
+        assert_eq!(branch.len(), 1);
+        assert_eq!(branch[0].content, "edited question");
+        assert_eq!(thread.branch_position(branch[0].id), Some((2, 2)));
+
+        thread.add_message(reply_at("edited answer", 3));
+
+        // the original conversation is still there, with everything that followed it
+        assert_eq!(thread.switch_branch(thread.head().unwrap(), true), None);
+        let edited_id = thread.non_sys_messages()[0].id;
+        assert_eq!(thread.switch_branch(edited_id, false), Some(original_id));
+        assert_eq!(thread.last_message().unwrap().content, "second answer");
+        assert_eq!(thread.messages().len(), 4);
+
+        assert_eq!(thread.switch_branch(original_id, true), Some(edited_id));
+        assert_eq!(thread.last_message().unwrap().content, "edited answer");
+
+        // replies can't be edited
+        let reply_id = thread.last_message().unwrap().id;
+        assert!(thread.edit_message(reply_id, "no").is_err());
+    }
 
-        let title = self.thread_title().unwrap_or("").to_string();
+    #[test]
+    fn test_discard_unanswered_edit() {
+        let mut thread = Thread::default();
+        thread.add_message(Message::new_user("question"));
+        thread.add_message(reply_at("answer", 1));
 
-        let display_time = if let Some(t) = self.init_time() {
-            t.to_string()
-        } else {
-            String::new()
-        };
+        let original_id = thread.non_sys_messages()[0].id;
+        thread.edit_message(original_id, "edited").unwrap();
 
-        let file_title = filenamify([display_time, title].join("_"));
+        let taken = thread.take_unanswered_message().unwrap();
+        assert_eq!(taken.content, "edited");
+        assert_eq!(thread.last_message().unwrap().content, "answer");
+        assert_eq!(thread.all_messages().len(), 2);
+    }
+
+    #[test]
+    fn test_fork_thread() {
+        let mut session = Session::in_memory();
+
+        let mut original = Thread::new(
+            vec![Message::new(Role::System, "prompt", Utc::now())],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+        original.add_message(Message::new_user("question"));
+        original.add_message(reply_at("answer", 1));
+        original.add_message(Message::new_user("follow up"));
+
+        let original_id = original.id;
+        let up_to = original.non_sys_messages()[1].id;
+        session.threads.insert(original_id, original);
+
+        let fork_id = session.fork_thread(original_id, Some(up_to)).unwrap();
+        let fork = session.thread_by_id(fork_id).unwrap();
+
+        assert_eq!(fork.forked_from(), Some(original_id));
+        assert_eq!(
+            fork.messages()
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect_vec(),
+            vec!["prompt", "question", "answer"]
+        );
+
+        // the copies are independent of the originals
+        let original = session.thread_by_id(original_id).unwrap();
+        assert!(fork
+            .all_messages()
+            .iter()
+            .all(|m| original.message_by_id(m.id).is_none()));
+
+        assert!(session
+            .fork_thread(original_id, Some(Uuid::new_v4()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_find_thread() {
+        let mut session = Session::in_memory();
 
-        let out_file = p.join(&file_title).with_extension("json");
+        let mut ids = Vec::new();
 
-        dbg!(&out_file);
+        for (i, (title, id)) in [
+            ("Rust lifetimes", "00000003-0000-4000-8000-000000000000"),
+            ("Python packaging", "5e7c1a2b-0000-4000-8000-000000000000"),
+            (
+                "Rust macros gone bad",
+                "0002beef-0000-4000-8000-000000000000",
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut thread = Thread::new(
+                vec![reply_at("question", i as i64 * 10)],
+                LlmModel::default(),
+                Uuid::parse_str(id).unwrap(),
+            );
+            thread.set_title(title);
+            ids.push(thread.id);
+            session.threads.insert(thread.id, thread);
+        }
 
-        out_file
+        let second = &session.threads[&ids[1]];
+
+        assert_eq!(session.find_thread("2").unwrap(), ids[1]);
+        assert_eq!(session.find_thread(&ids[1].to_string()).unwrap(), ids[1]);
+        assert_eq!(session.find_thread(&second.short_id()).unwrap(), ids[1]);
+        assert_eq!(session.find_thread("packag").unwrap(), ids[1]);
+        assert_eq!(session.find_thread("MACROS").unwrap(), ids[2]);
+
+        let ambiguous = session.find_thread("rust").unwrap_err().to_string();
+        assert!(ambiguous.contains("Rust lifetimes") && ambiguous.contains("Rust macros"));
+        assert!(session.find_thread("haskell").is_err());
+
+        // a short id made of digits is an id, not an index
+        assert_eq!(session.find_thread("00000003").unwrap(), ids[0]);
+        assert_eq!(session.find_thread("3").unwrap(), ids[2]);
+
+        // an index that's also the start of another thread's id isn't guessed at
+        let ambiguous = session.find_thread("0002").unwrap_err().to_string();
+        assert!(ambiguous.contains("Python packaging") && ambiguous.contains("Rust macros"));
+        assert_eq!(session.find_thread("0002b").unwrap(), ids[2]);
+
+        // short words are only looked for in titles, even if they could be the start of an id
+        assert_eq!(session.find_thread("bad").unwrap(), ids[2]);
+        assert!(session.find_thread("5e7").is_err());
+
+        // a thread with only a prompt isn't listed
+        let empty = Thread::with_prompt(&PromptSetting::default());
+        assert!(empty.is_empty());
+        session.threads.insert(empty.id, empty);
+        assert_eq!(session.ordered_threads().len(), 3);
+
+        // the most recent activity decides which thread is last, not when it was started
+        assert_eq!(session.last_thread(), Some(ids[2]));
+        session
+            .thread_by_id_mut(ids[0])
+            .unwrap()
+            .add_message(reply_at("late reply", 100));
+        assert_eq!(session.last_thread(), Some(ids[0]));
     }
 
-    pub fn dump(&self) {
-        self.dump_to_file(&self.dump_location())
+    #[test]
+    fn test_import_skips_duplicates() {
+        let mut session = Session::in_memory();
+
+        let mut thread = Thread::default();
+        thread.add_message(Message::new_user("question"));
+        thread.add_message(reply_at("answer", 1));
+
+        assert!(session.import_thread(thread.clone()).unwrap());
+        assert!(!session.import_thread(thread.clone()).unwrap());
+
+        // the same conversation under another id is still caught
+        let mut renamed = thread.clone();
+        renamed.id = Uuid::new_v4();
+        assert!(!session.import_thread(renamed).unwrap());
+
+        // and the import was saved
+        session.load_threads().unwrap();
+        assert_eq!(session.threads.len(), 1);
+        assert_eq!(session.threads[&thread.id].messages().len(), 2);
     }
 }
@@ -0,0 +1,285 @@
+use crate::message::{local_time_fmt, Segment};
+use crate::session::{Message, Role, Thread};
+
+use ratatui::style::Color;
+use std::io::{self, ErrorKind, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+
+/// Pager used when `$PAGER` isn't set
+const DEFAULT_PAGER: &str = "less";
+
+/// Options for `less` if none are set: leave short output on screen, and pass colors through
+const DEFAULT_LESS_OPTIONS: &str = "FRX";
+
+/// How a thread is printed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShowOptions {
+    /// Print only the code blocks
+    pub only_code: bool,
+
+    /// Print only this many of the latest messages, not counting the prompt
+    pub last: Option<usize>,
+
+    /// Color the output and highlight code. Otherwise messages are printed as they were written.
+    pub color: bool,
+}
+
+/// Foreground color escape code for one of the named terminal colors
+fn ansi_fg_code(color: Color) -> u8 {
+    match color {
+        Color::Black => 30,
+        Color::Red => 31,
+        Color::Green => 32,
+        Color::Yellow => 33,
+        Color::Blue => 34,
+        Color::Magenta => 35,
+        Color::Cyan => 36,
+        Color::Gray => 37,
+        Color::DarkGray => 90,
+        Color::LightRed => 91,
+        Color::LightGreen => 92,
+        Color::LightYellow => 93,
+        Color::LightBlue => 94,
+        Color::LightMagenta => 95,
+        Color::LightCyan => 96,
+        _ => 97,
+    }
+}
+
+/// The messages to print: the current branch, or only the latest of it
+fn shown_messages(thread: &Thread, options: ShowOptions) -> Vec<&Message> {
+    match options.last {
+        Some(n) => {
+            let messages = thread.non_sys_messages();
+            messages[messages.len().saturating_sub(n)..].to_vec()
+        }
+        None => thread.messages(),
+    }
+}
+
+fn write_header(out: &mut String, thread: &Thread, msg: &Message, options: ShowOptions) {
+    let header = thread.message_display_header(msg.role);
+    let time = local_time_fmt(msg.timestamp);
+
+    if options.color {
+        let color = header.style.fg.map(ansi_fg_code).unwrap_or(97);
+        out.push_str(&format!(
+            "{}\x1b[{}m{}{}  {}{}{}\n",
+            BOLD, color, header.content, RESET, DIM, time, RESET
+        ));
+    } else {
+        out.push_str(&format!("{}  {}\n", header.content, time));
+    }
+}
+
+/// A message's text, with code blocks highlighted if the output is colored
+fn write_content(out: &mut String, msg: &Message, options: ShowOptions) {
+    if !options.color {
+        out.push_str(msg.content.trim_end());
+        out.push('\n');
+        return;
+    }
+
+    for segment in msg.segments() {
+        match segment {
+            Segment::Code(block) => {
+                for line in block.lines_24_bit_terminal_escaped() {
+                    out.push_str(line);
+                    out.push_str(RESET);
+                    out.push('\n');
+                }
+            }
+            Segment::Text(text) => {
+                out.push_str(text.trim_matches('\n'));
+                out.push('\n');
+            }
+        }
+    }
+}
+
+/// A thread as text for a terminal, with each message under a header naming who sent it
+pub fn render_thread(thread: &Thread, options: ShowOptions) -> String {
+    let mut out = String::new();
+    let messages = shown_messages(thread, options);
+
+    if options.only_code {
+        for block in messages.iter().flat_map(|m| m.code_blocks()) {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+
+            if options.color {
+                for line in block.lines_24_bit_terminal_escaped() {
+                    out.push_str(line);
+                    out.push_str(RESET);
+                    out.push('\n');
+                }
+            } else {
+                // just the code, so it can be written straight to a file
+                out.push_str(&block.content);
+                out.push('\n');
+            }
+        }
+
+        return out;
+    }
+
+    if options.color {
+        out.push_str(&format!("{}{}{}\n", BOLD, thread.display_title(), RESET));
+    } else {
+        out.push_str(&format!("{}\n", thread.display_title()));
+    }
+
+    for msg in messages {
+        out.push('\n');
+        write_header(&mut out, thread, msg, options);
+
+        // the prompt is dimmed, to set it apart from the conversation
+        if msg.role == Role::System && options.color {
+            out.push_str(DIM);
+            out.push_str(msg.content.trim_end());
+            out.push_str(RESET);
+            out.push('\n');
+        } else {
+            write_content(&mut out, msg, options);
+        }
+
+        if let Some(note) = msg.truncation_note() {
+            if options.color {
+                out.push_str(&format!("{}{}{}\n", DIM, note, RESET));
+            } else {
+                out.push_str(note);
+                out.push('\n');
+            }
+        }
+    }
+
+    out
+}
+
+/// Print text through `$PAGER` if standard output is a terminal, or straight to standard output if not
+pub fn page_output(text: &str) -> crate::Result<()> {
+    if !io::stdout().is_terminal() {
+        io::stdout().write_all(text.as_bytes())?;
+        return Ok(());
+    }
+
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.into());
+    let mut pager_args = pager.split_whitespace();
+
+    let Some(program) = pager_args.next() else {
+        io::stdout().write_all(text.as_bytes())?;
+        return Ok(());
+    };
+
+    let mut command = Command::new(program);
+    command.args(pager_args).stdin(Stdio::piped());
+
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", DEFAULT_LESS_OPTIONS);
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+
+        // no pager to be had, so print it all instead
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            io::stdout().write_all(text.as_bytes())?;
+            return Ok(());
+        }
+
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(text.as_bytes()) {
+            // the pager was quit before reading everything
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => (),
+            result => result?,
+        }
+    }
+
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::LlmModel;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn example_thread() -> Thread {
+        let mut thread = Thread::new(
+            vec![Message::new(
+                Role::System,
+                "You are a helpful assistant",
+                Utc::now(),
+            )],
+            LlmModel::default(),
+            Uuid::new_v4(),
+        );
+
+        thread.add_message(Message::new_user("How do I print <b> in Rust?"));
+        thread.add_message(Message::new_asst(
+            "Like this:\n```rust\nprintln!(\"<b>\");\n```\nThat's all.",
+        ));
+        thread.set_title("Printing & escaping");
+
+        thread
+    }
+
+    #[test]
+    fn test_plain_output() {
+        let thread = example_thread();
+        let shown = render_thread(&thread, ShowOptions::default());
+
+        assert!(shown.starts_with("Printing & escaping\n"));
+        assert!(shown.contains("You are a helpful assistant\n"));
+        assert!(shown.contains("```rust\nprintln!(\"<b>\");\n```\nThat's all.\n"));
+        assert!(!shown.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_last_and_only_code() {
+        let mut thread = example_thread();
+        thread.add_message(Message::new_user("And in Python?"));
+        thread.add_message(Message::new_asst("```python\nprint(\"hi\")\n```"));
+
+        let options = ShowOptions {
+            last: Some(2),
+            ..Default::default()
+        };
+        let shown = render_thread(&thread, options);
+        assert!(shown.contains("And in Python?"));
+        assert!(!shown.contains("How do I print <b> in Rust?"));
+        assert!(!shown.contains("You are a helpful assistant"));
+
+        let options = ShowOptions {
+            only_code: true,
+            ..Default::default()
+        };
+        let shown = render_thread(&thread, options);
+        assert_eq!(shown, "println!(\"<b>\");\n\nprint(\"hi\")\n");
+    }
+
+    #[test]
+    fn test_colored_output_highlights_code() {
+        let thread = example_thread();
+
+        let options = ShowOptions {
+            color: true,
+            ..Default::default()
+        };
+        let shown = render_thread(&thread, options);
+
+        assert!(!shown.contains("```"));
+        assert!(shown.contains("\x1b[38;2;"));
+        assert!(shown.contains("That's all.\n"));
+    }
+}
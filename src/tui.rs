@@ -1,16 +1,18 @@
 use crate::editor::input_from_editor;
+use crate::input::InputEditor;
+use crate::message::local_time_fmt;
 use crate::session::string_preview;
 
-use crossbeam_channel::Receiver;
+use crossbeam_channel::TryRecvError;
 use ctrlc::set_handler;
 use itertools::Itertools;
 use ratatui::{
-    prelude::{Alignment, Constraint, CrosstermBackend, Direction, Layout},
+    prelude::{Alignment, Constraint, CrosstermBackend, Direction, Layout, Rect},
     style::{Color, Style, Stylize},
-    text::{Span, Text},
+    text::{Line, Span, Text},
     widgets::{
         block::{Position, Title},
-        Block, BorderType, Borders, Paragraph, Wrap,
+        Block, BorderType, Borders, Clear, List, ListItem, ListState, Paragraph,
     },
     Frame,
 };
@@ -27,23 +29,66 @@ use crossterm::{
 };
 use uuid::Uuid;
 
-use crate::client::stream_thread_reply;
+use crate::client::{stream_thread_reply, ReplyEvent, ReplyStream};
 use crate::clip;
+use crate::config::PromptSetting;
+use crate::picker::available_prompts;
+use crate::search::SearchResult;
 use crate::session::{Message, Session, Thread};
-type ReplyRx = Receiver<Option<String>>;
+use std::collections::HashMap;
 
 type Backend = ratatui::backend::CrosstermBackend<std::io::Stderr>;
-type CrosstermTerminal = ratatui::Terminal<Backend>;
+pub(crate) type CrosstermTerminal = ratatui::Terminal<Backend>;
 
 const FPS: f64 = 30.0;
 const SCROLL_STEP: usize = 1;
+const SEARCH_LIMIT: usize = 50;
+const SIDEBAR_WIDTH: u16 = 32;
+
+/// A reply being streamed into one of the session's threads
+struct PendingReply {
+    stream: ReplyStream,
+    retry_text: Option<String>,
+}
+
+/// State of the search overlay
+#[derive(Default)]
+struct SearchOverlay {
+    query: String,
+    results: Vec<SearchResult>,
+    selected: usize,
+}
+
+/// Get a rectangle in the middle of `area`, taking up the given percentage of its width and height
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
 
 pub struct App {
     should_quit: bool,
     session: Session,
     thread_id: Option<uuid::Uuid>,
-    reply_rx: Option<ReplyRx>,
-    user_message: String,
+    /// Replies still arriving, by the thread they belong to
+    replies: HashMap<Uuid, PendingReply>,
+    input: InputEditor,
+    /// Unsent input for threads other than the current one
+    drafts: HashMap<Uuid, InputEditor>,
     tick_duration: std::time::Duration,
     chat_scroll: usize,
     bottom_text: Option<String>,
@@ -54,6 +99,21 @@ pub struct App {
     text_len: usize,
     chat_window_height: u16,
     should_show_editor: bool,
+    confirm_keep_partial: bool,
+    error_text: Option<String>,
+    select_mode: bool,
+    selected_message: Option<Uuid>,
+    /// Message to scroll to, once the chat has been laid out
+    scroll_target: Option<Uuid>,
+    /// The message being edited, and the draft that was in the input box before editing started
+    editing: Option<(Uuid, InputEditor)>,
+    search: Option<SearchOverlay>,
+    sidebar_visible: bool,
+    /// Whether keys go to the sidebar rather than the chat
+    sidebar_focused: bool,
+    sidebar_selected: usize,
+    /// While choosing a prompt for a new thread, the index of the one highlighted
+    prompt_choice: Option<usize>,
 }
 
 macro_rules! resolve_thread_id {
@@ -85,8 +145,9 @@ macro_rules! app_defaults {
             should_quit: false,
             session: $session,
             thread_id: resolve_thread_id!($thread_id),
-            reply_rx: Default::default(),
-            user_message: String::new(),
+            replies: HashMap::new(),
+            input: InputEditor::default(),
+            drafts: HashMap::new(),
             chat_scroll: 0,
             text_len: 0,
             tick_duration,
@@ -97,6 +158,17 @@ macro_rules! app_defaults {
             content_line_width: 0,
             should_show_editor: false,
             chat_window_height: 0,
+            confirm_keep_partial: false,
+            error_text: None,
+            select_mode: false,
+            selected_message: None,
+            scroll_target: None,
+            editing: None,
+            search: None,
+            sidebar_visible: false,
+            sidebar_focused: false,
+            sidebar_selected: 0,
+            prompt_choice: None,
         })
     }};
 
@@ -225,6 +297,456 @@ impl App {
         Ok(())
     }
 
+    /// Stop receiving the current reply. If any of it has arrived,
+    /// ask the user whether to keep it.
+    fn cancel_reply(&mut self) -> crate::Result<()> {
+        if let Some(reply) = self.thread_id.and_then(|id| self.replies.remove(&id)) {
+            reply.stream.cancel();
+        }
+
+        if self.thread()?.incoming_message().is_some() {
+            self.confirm_keep_partial = true;
+            self.bottom_text = Some("Reply cancelled. Keep the partial reply? (y/n)".into());
+        } else {
+            self.discard_reply()?;
+            self.bottom_text = Some("Reply discarded".into());
+        }
+
+        Ok(())
+    }
+
+    /// Show an error from the reply stream. Any unanswered message goes back into the input box,
+    /// unless part of the reply had already arrived, in which case the user can choose to keep it.
+    fn reply_failed(&mut self, thread_id: Uuid, err: crate::Error) -> crate::Result<()> {
+        self.replies.remove(&thread_id);
+
+        if self.thread_id != Some(thread_id) {
+            return self.background_reply_failed(thread_id, err);
+        }
+
+        self.error_text = Some(err.to_string());
+
+        if self.thread()?.incoming_message().is_some() {
+            self.confirm_keep_partial = true;
+            self.bottom_text = Some("Keep the partial reply? (y/n)".into());
+        } else {
+            self.discard_reply()?;
+        }
+
+        Ok(())
+    }
+
+    /// A reply failed in a thread that isn't being shown, so there's nobody to ask what to do.
+    /// Whatever arrived is kept, and if nothing did, the unanswered message becomes that thread's draft.
+    fn background_reply_failed(&mut self, thread_id: Uuid, err: crate::Error) -> crate::Result<()> {
+        let Some(thread) = self.session.thread_by_id_mut(thread_id) else {
+            return Ok(());
+        };
+
+        if thread.incoming_message().is_some() {
+            thread.commit_truncated_message()?;
+        } else if !thread.restore_alternative() {
+            if let Some(msg) = thread.take_unanswered_message() {
+                self.drafts
+                    .entry(thread_id)
+                    .or_insert_with(|| InputEditor::with_text(&msg.content));
+            }
+        }
+
+        self.report_error(thread_id, err);
+        Ok(())
+    }
+
+    /// Show an error in the status line if it's about the current thread,
+    /// or as a notice naming the thread if not
+    fn report_error(&mut self, thread_id: Uuid, err: crate::Error) {
+        if self.thread_id == Some(thread_id) {
+            self.error_text = Some(err.to_string());
+        } else {
+            let title = self
+                .session
+                .thread_by_id(thread_id)
+                .map(|t| t.display_title())
+                .unwrap_or_default();
+
+            self.bottom_text = Some(format!("Error in '{}': {}", title, err));
+        }
+    }
+
+    /// Start streaming a reply to the last message in the thread
+    fn request_reply(&mut self) -> crate::Result<()> {
+        self.error_text = None;
+
+        let thread_id = self.thread()?.id;
+
+        match stream_thread_reply(self.thread()?) {
+            Ok(stream) => {
+                self.replies.insert(
+                    thread_id,
+                    PendingReply {
+                        stream,
+                        retry_text: None,
+                    },
+                );
+            }
+            Err(e) => self.reply_failed(thread_id, e)?,
+        }
+
+        Ok(())
+    }
+
+    /// Stop every reply still arriving, keeping whatever has been received of each
+    fn cancel_all_replies(&mut self) -> crate::Result<()> {
+        for (thread_id, reply) in self.replies.drain().collect_vec() {
+            reply.stream.cancel();
+
+            if let Some(thread) = self.session.thread_by_id_mut(thread_id) {
+                if thread.incoming_message().is_some() {
+                    thread.commit_truncated_message()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make another thread the current one. Anything typed so far is kept for when it's shown again.
+    fn switch_thread(&mut self, thread_id: Uuid) {
+        if self.thread_id == Some(thread_id) {
+            return;
+        }
+
+        // the message being edited, and any other mode, belongs to the thread being left
+        self.cancel_editing();
+        self.exit_copy_mode();
+        if self.select_mode {
+            self.exit_select_mode();
+        }
+
+        if let Some(old_id) = self.thread_id {
+            let draft = std::mem::take(&mut self.input);
+            if !draft.is_empty() {
+                self.drafts.insert(old_id, draft);
+            }
+        }
+
+        self.input = self.drafts.remove(&thread_id).unwrap_or_default();
+        self.thread_id = Some(thread_id);
+        self.bottom_text = None;
+        self.error_text = None;
+        self.chat_scroll = usize::MAX;
+    }
+
+    /// Add the user's message to the thread and start streaming a reply to it
+    fn send_message(&mut self) -> crate::Result<()> {
+        let new_message = Message::new_user(&self.input.take());
+        self.thread_mut()?.add_message(new_message);
+
+        self.request_reply()
+    }
+
+    /// Set aside the last reply and request a new one in its place
+    fn regenerate_reply(&mut self) -> crate::Result<()> {
+        match self.thread_mut()?.regenerate_reply() {
+            Ok(()) => self.request_reply(),
+            Err(e) => {
+                self.bottom_text = Some(e.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Throw away a cancelled reply. If it was a regenerated reply, the previous one is put back.
+    /// Otherwise the message it was replying to goes back to the input box so it can be edited or resent.
+    fn discard_reply(&mut self) -> crate::Result<()> {
+        let thread = self.thread_mut()?;
+        thread.clear_incoming_message();
+
+        if thread.restore_alternative() {
+            return Ok(());
+        }
+
+        let unanswered = thread.take_unanswered_message();
+
+        // an edited message goes back to being edited, so resending it branches again
+        let edited_from = unanswered.as_ref().and_then(|msg| {
+            thread
+                .messages()
+                .into_iter()
+                .find(|m| m.is_user() && m.parent_id == msg.parent_id)
+                .map(|m| m.id)
+        });
+
+        if let Some(msg) = unanswered {
+            if let Some(original_id) = edited_from {
+                let draft =
+                    std::mem::replace(&mut self.input, InputEditor::with_text(&msg.content));
+                self.editing = Some((original_id, draft));
+            } else if self.input.is_empty() {
+                self.input.set_text(&msg.content);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copy the branch currently shown into a new thread, and switch to it
+    fn fork_thread(&mut self) -> crate::Result<()> {
+        let thread_id = self.thread()?.id;
+        let fork_id = self.session.fork_thread(thread_id, None)?;
+
+        self.switch_thread(fork_id);
+        self.bottom_text = Some("Forked into a new thread".into());
+
+        Ok(())
+    }
+
+    /// Open the search overlay. Threads are saved first, so this session's messages can be found too.
+    fn open_search(&mut self) -> crate::Result<()> {
+        self.session.save_to_db()?;
+        self.search = Some(SearchOverlay::default());
+        Ok(())
+    }
+
+    /// 'minor mode' for searching all threads, and jumping to a result
+    fn update_search(&mut self, key_event: KeyEvent) -> crate::Result<()> {
+        let Some(overlay) = self.search.as_mut() else {
+            return Ok(());
+        };
+
+        let query_changed = match key_event.code {
+            KeyCode::Esc => {
+                self.search = None;
+                return Ok(());
+            }
+            KeyCode::Enter => {
+                let selected = overlay.results.get(overlay.selected).cloned();
+                self.search = None;
+
+                if let Some(result) = selected {
+                    self.open_search_result(result)?;
+                }
+
+                return Ok(());
+            }
+            KeyCode::Up => {
+                overlay.selected = overlay.selected.saturating_sub(1);
+                false
+            }
+            KeyCode::Down => {
+                if overlay.selected + 1 < overlay.results.len() {
+                    overlay.selected += 1;
+                }
+                false
+            }
+            KeyCode::Backspace => overlay.query.pop().is_some(),
+            KeyCode::Char(c) if !key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                overlay.query.push(c);
+                true
+            }
+            _ => false,
+        };
+
+        if query_changed {
+            let query = overlay.query.clone();
+
+            match self.session.search(&query, SEARCH_LIMIT) {
+                Ok(results) => {
+                    if let Some(overlay) = self.search.as_mut() {
+                        overlay.results = results;
+                        overlay.selected = 0;
+                    }
+                }
+                Err(e) => self.bottom_text = Some(e.to_string()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch to the thread a search result came from, scrolled to the message that matched
+    fn open_search_result(&mut self, result: SearchResult) -> crate::Result<()> {
+        if self.session.thread_by_id(result.thread_id).is_none() {
+            self.bottom_text = Some("That thread is no longer available".into());
+            return Ok(());
+        }
+
+        self.switch_thread(result.thread_id);
+
+        match result.message_id {
+            Some(message_id) => self.show_message(message_id)?,
+            None => self.chat_scroll = 0,
+        }
+
+        Ok(())
+    }
+
+    /// Ids of the user's messages in the branch currently shown
+    fn user_message_ids(&self) -> crate::Result<Vec<Uuid>> {
+        Ok(self
+            .thread()?
+            .messages()
+            .into_iter()
+            .filter(|m| m.is_user())
+            .map(|m| m.id)
+            .collect())
+    }
+
+    fn select_message(&mut self, id: Uuid) {
+        self.selected_message = Some(id);
+        self.scroll_target = Some(id);
+    }
+
+    /// Show the given message in the current thread, switching branches if needed, and scroll to it
+    pub fn show_message(&mut self, id: Uuid) -> crate::Result<()> {
+        if self.thread_mut()?.show_message(id) {
+            self.scroll_target = Some(id);
+        }
+
+        Ok(())
+    }
+
+    fn enter_select_mode(&mut self) -> crate::Result<()> {
+        match self.user_message_ids()?.last() {
+            Some(&id) => {
+                self.select_mode = true;
+                self.select_message(id);
+                self.bottom_text = Some(
+                    "Up/Down to select, Enter to edit, ctrl-e to edit in editor, alt-left/right to switch versions, Esc to go back"
+                        .into(),
+                );
+            }
+            None => self.bottom_text = Some("No messages to edit".into()),
+        }
+
+        Ok(())
+    }
+
+    fn exit_select_mode(&mut self) {
+        self.select_mode = false;
+        self.selected_message = None;
+        self.scroll_target = None;
+        self.bottom_text = None;
+    }
+
+    /// 'minor mode' for choosing one of the user's earlier messages to edit
+    fn update_select_mode(&mut self, key_event: KeyEvent) -> crate::Result<()> {
+        let Some(selected) = self.selected_message else {
+            self.exit_select_mode();
+            return Ok(());
+        };
+
+        let ids = self.user_message_ids()?;
+        let position = ids
+            .iter()
+            .position(|id| *id == selected)
+            .unwrap_or_default();
+
+        match key_event.code {
+            KeyCode::Esc => self.exit_select_mode(),
+            KeyCode::Char('p') if matches!(key_event.modifiers, KeyModifiers::CONTROL) => {
+                self.exit_select_mode()
+            }
+
+            // switch between versions of the selected message
+            KeyCode::Left | KeyCode::Right if matches!(key_event.modifiers, KeyModifiers::ALT) => {
+                let forward = key_event.code == KeyCode::Right;
+                if let Some(id) = self.thread_mut()?.switch_branch(selected, forward) {
+                    self.select_message(id);
+                }
+            }
+
+            KeyCode::Up => {
+                if let Some(&id) = position.checked_sub(1).and_then(|i| ids.get(i)) {
+                    self.select_message(id);
+                }
+            }
+            KeyCode::Down => {
+                if let Some(&id) = ids.get(position + 1) {
+                    self.select_message(id);
+                }
+            }
+
+            KeyCode::Enter => self.start_editing(selected)?,
+            KeyCode::Char('e') if matches!(key_event.modifiers, KeyModifiers::CONTROL) => {
+                self.start_editing(selected)?;
+                self.should_show_editor = true;
+            }
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Put an earlier message in the input box, to be resent as a new branch
+    fn start_editing(&mut self, id: Uuid) -> crate::Result<()> {
+        let content = match self.thread()?.message_by_id(id) {
+            Some(msg) => msg.content.clone(),
+            None => return Ok(()),
+        };
+
+        self.exit_select_mode();
+
+        let draft = std::mem::replace(&mut self.input, InputEditor::with_text(&content));
+
+        // editing something else already: keep the original draft
+        let draft = match self.editing.take() {
+            Some((_, original_draft)) => original_draft,
+            None => draft,
+        };
+
+        self.editing = Some((id, draft));
+        self.bottom_text = Some("Editing message: alt-enter to resend, Esc to cancel".into());
+
+        Ok(())
+    }
+
+    fn cancel_editing(&mut self) {
+        if let Some((_, draft)) = self.editing.take() {
+            self.input = draft;
+            self.bottom_text = Some("Edit cancelled".into());
+        }
+    }
+
+    /// Resend an edited message, branching the thread from the point it was originally sent
+    fn send_edited_message(&mut self) -> crate::Result<()> {
+        let Some((id, draft)) = self.editing.take() else {
+            return Ok(());
+        };
+
+        let content = std::mem::replace(&mut self.input, draft).take();
+
+        if let Err(e) = self.thread_mut()?.edit_message(id, &content) {
+            self.bottom_text = Some(e.to_string());
+            return Ok(());
+        }
+
+        self.bottom_text = None;
+        self.chat_scroll = self.max_scroll();
+        self.request_reply()
+    }
+
+    /// 'minor mode' after cancelling a reply, asking whether to keep what was received
+    fn update_confirm_keep_partial(&mut self, key_event: KeyEvent) -> crate::Result<()> {
+        match key_event.code {
+            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                self.bottom_text = None;
+                if let Err(e) = self.thread_mut()?.commit_truncated_message() {
+                    self.error_text = Some(e.to_string());
+                }
+            }
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                self.discard_reply()?;
+                self.bottom_text = Some("Reply discarded".into());
+            }
+            _ => return Ok(()),
+        }
+
+        self.confirm_keep_partial = false;
+        Ok(())
+    }
+
     fn scroll_up(&mut self, step: usize) {
         self.chat_scroll = self.chat_scroll.saturating_sub(step);
     }
@@ -255,11 +777,36 @@ impl App {
                     self.should_quit = true;
                 }
 
-                //scroll history up
-                KeyCode::Up => self.scroll_up(SCROLL_STEP),
+                // waiting on a y/n answer about a cancelled reply
+                _ if self.confirm_keep_partial => self.update_confirm_keep_partial(key_event)?,
+
+                // searching
+                _ if self.search.is_some() => self.update_search(key_event)?,
+
+                // choosing a message to edit
+                _ if self.select_mode => self.update_select_mode(key_event)?,
+
+                // moving around the thread list
+                _ if self.sidebar_focused => self.update_sidebar(key_event)?,
+
+                // ctrl-b to show the thread list
+                KeyCode::Char('b') if matches!(key_modifiers, KeyModifiers::CONTROL) => {
+                    self.toggle_sidebar();
+                }
+
+                // move between the lines of the message being written,
+                // or scroll the history once there are no more lines to move to
+                KeyCode::Up => {
+                    if self.copy_mode || !self.input.move_up() {
+                        self.scroll_up(SCROLL_STEP);
+                    }
+                }
 
-                // scroll history down
-                KeyCode::Down => self.scroll_down(SCROLL_STEP),
+                KeyCode::Down => {
+                    if self.copy_mode || !self.input.move_down() {
+                        self.scroll_down(SCROLL_STEP);
+                    }
+                }
 
                 // if already in copy mode, forward event to its handler
                 _ if self.copy_mode => self.update_copy_mode(key_event)?,
@@ -273,39 +820,50 @@ impl App {
                 KeyCode::Char('e') if matches!(key_modifiers, KeyModifiers::CONTROL) => {
                     self.should_show_editor = true;
                 }
-                //submit the message with alt-enter
-                KeyCode::Enter if matches!(key_modifiers, KeyModifiers::ALT) => {
-                    if !self.user_message.is_empty() {
-                        let new_message = Message::new_user(&self.user_message);
-                        self.thread_mut()?.add_message(new_message);
-
-                        self.reply_rx = Some(stream_thread_reply(self.thread()?)?);
 
-                        self.user_message.clear();
-                    }
+                // ctrl-p to pick an earlier message to edit
+                KeyCode::Char('p') if matches!(key_modifiers, KeyModifiers::CONTROL) => {
+                    self.enter_select_mode()?;
                 }
 
-                // insert a newline
-                KeyCode::Enter => {
-                    self.user_message.push('\n');
+                // ctrl-s to search all threads
+                KeyCode::Char('s') if matches!(key_modifiers, KeyModifiers::CONTROL) => {
+                    self.open_search()?;
                 }
 
-                // enter uppercase char
-                KeyCode::Char(c) if matches!(key_modifiers, KeyModifiers::SHIFT) => {
-                    self.user_message.push(c.to_ascii_uppercase());
+                // ctrl-f to continue in a copy of this thread
+                KeyCode::Char('f') if matches!(key_modifiers, KeyModifiers::CONTROL) => {
+                    self.fork_thread()?;
                 }
 
-                // enter lowercase char
-                KeyCode::Char(c) => {
-                    self.user_message.push(c);
+                // Esc to stop editing an earlier message
+                KeyCode::Esc if self.editing.is_some() => self.cancel_editing(),
+
+                // ctrl-r to regenerate the last reply
+                KeyCode::Char('r') if matches!(key_modifiers, KeyModifiers::CONTROL) => {
+                    self.regenerate_reply()?;
                 }
 
-                // delete last char
-                KeyCode::Backspace => {
-                    self.user_message.pop();
+                // alt-left/right to switch between regenerated replies
+                KeyCode::Left | KeyCode::Right if matches!(key_modifiers, KeyModifiers::ALT) => {
+                    let forward = key_code == KeyCode::Right;
+                    self.thread_mut()?.cycle_alternative(forward);
+                }
+                //submit the message with alt-enter
+                KeyCode::Enter
+                    if matches!(key_modifiers, KeyModifiers::ALT) && !self.input.is_empty() =>
+                {
+                    if self.editing.is_some() {
+                        self.send_edited_message()?;
+                    } else {
+                        self.send_message()?;
+                    }
                 }
 
-                _ => (),
+                // anything else edits the message being written
+                _ => {
+                    self.input.handle_key(key_event);
+                }
             }
             // non-keyboard events
         } else {
@@ -327,65 +885,355 @@ impl App {
         Ok(())
     }
 
+    /// Whether a reply is arriving in the current thread
     fn is_recieving(&self) -> bool {
-        self.reply_rx.is_some()
+        self.thread_id
+            .is_some_and(|id| self.replies.contains_key(&id))
     }
 
-    fn update_recieving(&mut self) -> crate::Result<()> {
-        self.chat_scroll = self.max_scroll();
-        if let Some(rx) = self.reply_rx.as_ref() {
-            {
-                match rx.recv()? {
-                    Some(s) => {
-                        self.thread_mut()?.update(&s);
+    /// Handle input while a reply is arriving: Esc or ctrl-c cancels it,
+    /// and the thread list can be used to switch to another thread in the meantime
+    fn update_recieving_input(&mut self) -> crate::Result<()> {
+        if let Event::Key(
+            key_event @ KeyEvent {
+                kind: event::KeyEventKind::Press,
+                code,
+                modifiers,
+                ..
+            },
+        ) = crossterm::event::read()?
+        {
+            match code {
+                KeyCode::Char('c') if matches!(modifiers, KeyModifiers::CONTROL) => {
+                    self.cancel_reply()?
+                }
+                _ if self.sidebar_focused => self.update_sidebar(key_event)?,
+                KeyCode::Char('b') if matches!(modifiers, KeyModifiers::CONTROL) => {
+                    self.toggle_sidebar()
+                }
+                KeyCode::Esc => self.cancel_reply()?,
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take every event that has arrived for a thread's reply so far, without blocking on the next one
+    fn update_reply(&mut self, thread_id: Uuid) -> crate::Result<()> {
+        while let Some(rx) = self.replies.get(&thread_id).map(|r| r.stream.rx.clone()) {
+            match rx.try_recv() {
+                Ok(ReplyEvent::Token(s)) => {
+                    if let Some(reply) = self.replies.get_mut(&thread_id) {
+                        reply.retry_text = None;
+                    }
+
+                    if let Some(thread) = self.session.thread_by_id_mut(thread_id) {
+                        thread.update(&s);
+                    }
+                }
+                Ok(ReplyEvent::Retrying {
+                    attempt,
+                    max_retries,
+                    delay,
+                    reason,
+                }) => {
+                    if let Some(reply) = self.replies.get_mut(&thread_id) {
+                        reply.retry_text = Some(format!(
+                            "Retrying ({}/{}) in {:.0}s: {}",
+                            attempt,
+                            max_retries,
+                            delay.as_secs_f64().ceil(),
+                            reason
+                        ));
                     }
-                    None => {
-                        self.thread_mut()?.commit_message()?;
-                        self.reply_rx = None;
+                }
+                Ok(ReplyEvent::Done) => {
+                    self.replies.remove(&thread_id);
+
+                    // the reply itself is already committed if naming the thread fails
+                    if let Some(Err(e)) = self
+                        .session
+                        .thread_by_id_mut(thread_id)
+                        .map(|t| t.commit_message())
+                    {
+                        self.report_error(thread_id, e);
                     }
                 }
+                Ok(ReplyEvent::Error(e)) => self.reply_failed(thread_id, e)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.reply_failed(
+                    thread_id,
+                    crate::Error::ChannelError("Reply stream ended unexpectedly".into()),
+                )?,
             }
         }
 
         Ok(())
     }
 
+    /// Keep every thread's reply moving, whether or not it's the one being shown
+    fn update_replies(&mut self) -> crate::Result<()> {
+        if self.is_recieving() {
+            self.chat_scroll = self.max_scroll();
+        }
+
+        for thread_id in self.replies.keys().copied().collect_vec() {
+            self.update_reply(thread_id)?;
+        }
+
+        Ok(())
+    }
+
     fn update(&mut self) -> crate::Result<()> {
         let has_key_input = crossterm::event::poll(self.tick_duration)?;
 
-        match self.reply_rx {
-            None if has_key_input => {
+        if has_key_input {
+            if self.is_recieving() {
+                self.update_recieving_input()?;
+            } else {
                 self.update_awaiting_send()?;
             }
+        }
+
+        self.update_replies()
+    }
+
+    /// Ids of the threads listed in the sidebar, most recent first.
+    /// The current thread is always listed, even before anything has been sent in it.
+    fn sidebar_threads(&self) -> Vec<Uuid> {
+        let mut ids = self
+            .session
+            .ordered_threads()
+            .into_iter()
+            .rev()
+            .map(|(id, _)| *id)
+            .collect_vec();
+
+        if let Some(current) = self.thread_id.filter(|id| !ids.contains(id)) {
+            ids.insert(0, current);
+        }
+
+        ids
+    }
+
+    /// ctrl-b shows the sidebar, moves into it if it's already shown, and hides it from inside
+    fn toggle_sidebar(&mut self) {
+        if self.sidebar_focused {
+            self.sidebar_visible = false;
+            self.sidebar_focused = false;
+            self.prompt_choice = None;
+            return;
+        }
+
+        self.sidebar_visible = true;
+        self.sidebar_focused = true;
+        self.sidebar_selected = self
+            .thread_id
+            .and_then(|current| self.sidebar_threads().iter().position(|id| *id == current))
+            .unwrap_or_default();
+    }
+
+    /// Start a new thread with the given prompt, and switch to it
+    fn new_thread(&mut self, prompt: &PromptSetting) -> crate::Result<()> {
+        let thread_id = self.session.new_thread(prompt)?;
+        self.switch_thread(thread_id);
+        Ok(())
+    }
 
-            Some(_) => {
-                // flush keyboard input while recieving
-                if has_key_input {
-                    let _ = crossterm::event::read();
+    /// 'minor mode' for choosing a thread to switch to, or a prompt to start a new one with
+    fn update_sidebar(&mut self, key_event: KeyEvent) -> crate::Result<()> {
+        if let Some(selected) = self.prompt_choice {
+            let prompts = available_prompts();
+
+            match key_event.code {
+                KeyCode::Esc => self.prompt_choice = None,
+                KeyCode::Up => self.prompt_choice = Some(selected.saturating_sub(1)),
+                KeyCode::Down => {
+                    self.prompt_choice = Some((selected + 1).min(prompts.len().saturating_sub(1)))
+                }
+                KeyCode::Enter => {
+                    self.prompt_choice = None;
+                    self.sidebar_focused = false;
+
+                    if let Some(prompt) = prompts.get(selected) {
+                        self.new_thread(prompt)?;
+                    }
                 }
-                self.update_recieving()?;
+                _ => (),
             }
 
+            return Ok(());
+        }
+
+        let threads = self.sidebar_threads();
+
+        match key_event.code {
+            KeyCode::Esc => self.sidebar_focused = false,
+            KeyCode::Char('b') if matches!(key_event.modifiers, KeyModifiers::CONTROL) => {
+                self.toggle_sidebar()
+            }
+            KeyCode::Up => self.sidebar_selected = self.sidebar_selected.saturating_sub(1),
+            KeyCode::Down => {
+                self.sidebar_selected =
+                    (self.sidebar_selected + 1).min(threads.len().saturating_sub(1))
+            }
+            KeyCode::Enter => {
+                if let Some(&thread_id) = threads.get(self.sidebar_selected) {
+                    self.sidebar_focused = false;
+                    self.switch_thread(thread_id);
+                }
+            }
+            KeyCode::Char('n') => self.prompt_choice = Some(0),
             _ => (),
         }
 
         Ok(())
     }
 
+    fn render_sidebar(&self, frame: &mut Frame, area: Rect) {
+        let border_style = if self.sidebar_focused {
+            Style::default().fg(Color::Cyan)
+        } else {
+            Style::default()
+        };
+
+        let (title, help) = if self.prompt_choice.is_some() {
+            ("New thread", "Enter: start, Esc: back")
+        } else {
+            ("Threads", "Enter: open, n: new, Esc: back")
+        };
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(border_style)
+            .title(title)
+            .title(
+                Title::from(help)
+                    .position(Position::Bottom)
+                    .alignment(Alignment::Left),
+            );
+
+        let item_width = area.width.saturating_sub(4) as usize;
+
+        if let Some(selected) = self.prompt_choice {
+            let items = available_prompts()
+                .into_iter()
+                .map(|p| {
+                    ListItem::new(Line::from(vec![
+                        Span::from(string_preview(&p.label, item_width).to_string()).bold(),
+                        Span::from(format!(" ({})", p.model)).dark_gray(),
+                    ]))
+                })
+                .collect_vec();
+
+            let mut list_state = ListState::default().with_selected(Some(selected));
+            frame.render_stateful_widget(
+                List::new(items)
+                    .block(block)
+                    .highlight_style(Style::default().reversed()),
+                area,
+                &mut list_state,
+            );
+
+            return;
+        }
+
+        let threads = self.sidebar_threads();
+
+        let items = threads
+            .iter()
+            .filter_map(|id| self.session.thread_by_id(*id))
+            .map(|thread| {
+                let title = if thread.is_empty() {
+                    "(new thread)".to_string()
+                } else {
+                    thread.display_title()
+                };
+
+                // a marker for threads with a reply still arriving
+                let marker = if self.replies.contains_key(&thread.id) {
+                    Span::from("* ").yellow().bold()
+                } else {
+                    Span::from("  ")
+                };
+
+                let mut title = Span::from(string_preview(&title, item_width).to_string());
+                if Some(thread.id) == self.thread_id {
+                    title = title.cyan().bold();
+                }
+
+                ListItem::new(Line::from(vec![marker, title]))
+            })
+            .collect_vec();
+
+        let selected = if self.sidebar_focused {
+            Some(self.sidebar_selected)
+        } else {
+            self.thread_id
+                .and_then(|current| threads.iter().position(|id| *id == current))
+        };
+
+        let highlight_style = if self.sidebar_focused {
+            Style::default().reversed()
+        } else {
+            Style::default()
+        };
+
+        let mut list_state = ListState::default().with_selected(selected);
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(block)
+                .highlight_style(highlight_style),
+            area,
+            &mut list_state,
+        );
+    }
+
     fn ui(&mut self, frame: &mut Frame) -> crate::Result<()> {
         let h_padding = 5u16;
 
+        let area = if self.sidebar_visible {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(SIDEBAR_WIDTH), Constraint::Min(0)])
+                .split(frame.size());
+
+            self.render_sidebar(frame, columns[0]);
+            columns[1]
+        } else {
+            frame.size()
+        };
+
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
             .constraints([Constraint::Percentage(80), Constraint::Percentage(20)].as_ref())
-            .split(frame.size());
+            .split(area);
+
+        self.content_line_width = chunks[0]
+            .width
+            .saturating_sub(h_padding * 2)
+            .saturating_sub(2);
 
-        self.content_line_width = chunks[0].width - (h_padding * 2) - 2;
+        let highlighted = self
+            .selected_message
+            .or(self.editing.as_ref().map(|(id, _)| *id));
 
         let msgs_formatted = self
             .thread()?
-            .tui_formatted_messages(self.content_line_width);
+            .tui_formatted_messages(self.content_line_width, highlighted);
+
+        // line each message starts on, to scroll to a selected message
+        let msg_offsets = msgs_formatted
+            .iter()
+            .scan(0usize, |offset, m| {
+                let start = *offset;
+                *offset += m.lines.len();
+                Some(start)
+            })
+            .collect_vec();
 
         let msg_lines = msgs_formatted
             .into_iter()
@@ -394,10 +1242,23 @@ impl App {
 
         let text_len = msg_lines.len();
 
+        let target_offset = self
+            .scroll_target
+            .and_then(|id| {
+                self.thread()
+                    .ok()?
+                    .non_sys_messages()
+                    .iter()
+                    .position(|m| m.id == id)
+            })
+            .and_then(|i| msg_offsets.get(i).copied());
+
         let msgs_text = self.visible_text(msg_lines);
 
         let (border_color, border_type) = if self.copy_mode {
             (Color::Magenta, BorderType::Thick)
+        } else if self.select_mode {
+            (Color::Yellow, BorderType::Thick)
         } else {
             (Color::default(), BorderType::Rounded)
         };
@@ -412,8 +1273,25 @@ impl App {
 
         let chat_title = self.thread()?.display_title();
 
-        let status_message: Title<'_> = if self.is_recieving() {
+        let retry_text = self
+            .thread_id
+            .and_then(|id| self.replies.get(&id))
+            .and_then(|r| r.retry_text.as_deref());
+
+        let status_message: Title<'_> = if let Some(retry_text) = retry_text {
+            let retry_preview = string_preview(retry_text, self.content_line_width.into());
+            Span::from(format!("[{}]", retry_preview))
+                .yellow()
+                .bold()
+                .into()
+        } else if self.is_recieving() {
             Span::from("[Please Wait]").red().bold().into()
+        } else if let Some(error_text) = self.error_text.as_deref() {
+            let error_preview = string_preview(error_text, self.content_line_width.into());
+            Span::from(format!("[Error: {}]", error_preview))
+                .red()
+                .bold()
+                .into()
         } else {
             Span::from("[Ready!]").green().into()
         };
@@ -462,17 +1340,118 @@ impl App {
                 .title_position(ratatui::widgets::block::Position::Bottom);
         }
 
-        let input_widget = Paragraph::new(self.user_message.as_str())
-            .wrap(Wrap { trim: false })
-            .block(input_block);
+        let input_focused = !self.is_recieving()
+            && !self.copy_mode
+            && !self.select_mode
+            && !self.sidebar_focused
+            && !self.confirm_keep_partial
+            && self.search.is_none();
 
-        frame.render_widget(input_widget, chunks[1]);
+        self.input
+            .render(frame, chunks[1], input_block, input_focused);
+
+        if self.search.is_some() {
+            self.render_search(frame);
+        }
 
         self.text_len = text_len;
         self.chat_window_height = chunks[0].height;
+
+        // a thread that was just switched to starts at the end
+        self.chat_scroll = self.chat_scroll.min(self.max_scroll());
+
+        // takes effect from the next frame
+        if self.scroll_target.take().is_some() {
+            if let Some(offset) = target_offset {
+                self.chat_scroll = offset.min(self.max_scroll());
+            }
+        }
+
         Ok(())
     }
 
+    fn render_search(&self, frame: &mut Frame) {
+        let Some(overlay) = self.search.as_ref() else {
+            return;
+        };
+
+        let area = centered_rect(80, 70, frame.size());
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Thick)
+            .border_style(Style::default().fg(Color::Cyan))
+            .title("Search")
+            .title(
+                Title::from("Up/Down to select, Enter to open, Esc to close")
+                    .position(Position::Bottom)
+                    .alignment(Alignment::Left),
+            );
+
+        let inner = block.inner(area);
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(2), Constraint::Min(0)])
+            .split(inner);
+
+        let query_line = Line::from(vec![
+            Span::from("> ").cyan().bold(),
+            Span::from(overlay.query.as_str()),
+        ]);
+        frame.render_widget(Paragraph::new(query_line), chunks[0]);
+
+        let items = overlay
+            .results
+            .iter()
+            .map(|result| {
+                let thread = self
+                    .session
+                    .thread_by_id(result.thread_id)
+                    .expect("Failed to fetch thread");
+
+                let date = result
+                    .timestamp
+                    .or_else(|| thread.init_time())
+                    .map(local_time_fmt)
+                    .unwrap_or_default();
+
+                let heading = Line::from(vec![
+                    Span::from(thread.display_title()).bold(),
+                    Span::from(format!(" {}", date)).dark_gray(),
+                ]);
+
+                let source = match result.role {
+                    Some(role) => thread.message_display_header(role),
+                    None => Span::from("Title").italic(),
+                };
+
+                let snippet = Line::from(
+                    [source, Span::from(": ")]
+                        .into_iter()
+                        .chain(result.snippet_spans())
+                        .collect_vec(),
+                );
+
+                ListItem::new(vec![heading, snippet, Line::from("")])
+            })
+            .collect_vec();
+
+        let no_results = items.is_empty() && !overlay.query.trim().is_empty();
+
+        let list = List::new(items).highlight_style(Style::default().reversed());
+        let mut list_state = ListState::default().with_selected(Some(overlay.selected));
+
+        if no_results {
+            frame.render_widget(Paragraph::new("No results").dark_gray(), chunks[1]);
+        } else {
+            frame.render_stateful_widget(list, chunks[1], &mut list_state);
+        }
+    }
+
     pub fn with_thread(session: Session, thread_id: Uuid) -> crate::Result<Self> {
         app_defaults!(session, thread_id)
     }
@@ -493,10 +1472,16 @@ impl App {
 
         let mut terminal = CrosstermTerminal::new(CrosstermBackend::new(std::io::stderr()))?;
 
+        // start at the end of the conversation, unless asked to show a particular message
+        let scroll_to_end = self.scroll_target.is_none();
+
         // initial draw to initialize internal ui state variables
         self.update()?;
         terminal.draw(|frame| self.ui(frame).unwrap())?;
-        self.chat_scroll = self.max_scroll();
+
+        if scroll_to_end {
+            self.chat_scroll = self.max_scroll();
+        }
 
         while !self.should_quit {
             self.update()?;
@@ -509,6 +1494,8 @@ impl App {
             }
         }
 
+        self.cancel_all_replies()?;
+
         App::shutdown()?;
         Ok(())
     }
@@ -517,8 +1504,8 @@ impl App {
         terminal.clear()?;
         terminal.flush()?;
 
-        if let Some(editor_input) = input_from_editor(&self.user_message)? {
-            self.user_message = editor_input;
+        if let Some(editor_input) = input_from_editor(self.input.text())? {
+            self.input.set_text(&editor_input);
         }
 
         App::startup()?;